@@ -17,12 +17,16 @@ pub fn run(arguments: impl IntoIterator<Item = String>) -> Result<(), Failure> {
         .parse()
         .map_err(Failure::UnknownCommand)?;
 
-    let mut controller = Controller::connect(SOCKET_PATH).map_err(Failure::ControllerError)?;
+    let connect = || Controller::connect(SOCKET_PATH).map_err(Failure::ControllerError);
 
     match command {
-        Command::Application => modules::application(&mut controller, arguments)?,
+        Command::Application => modules::application(&mut connect()?, arguments)?,
+        Command::Apply => modules::apply(connect, arguments)?,
+        Command::Audit => modules::audit(&mut connect()?, arguments)?,
+        Command::Configuration => modules::configuration(connect, arguments)?,
         Command::Help => modules::help(),
-        Command::Status => modules::status(&mut controller),
+        Command::Secret => modules::secret(&mut connect()?, arguments)?,
+        Command::Status => modules::status(&mut connect()?),
     };
 
     Ok(())
@@ -3,9 +3,13 @@ pub enum Command {
     Help,
     Status,
     Application,
+    Audit,
+    Configuration,
+    Apply,
+    Secret,
 }
 
-// TODO: we want more here, a way to view docker logs, and a way to rotate upload secret
+// TODO: we want more here, a way to view docker logs
 
 impl std::str::FromStr for Command {
     type Err = String;
@@ -13,7 +17,11 @@ impl std::str::FromStr for Command {
     fn from_str(string: &str) -> Result<Self, Self::Err> {
         match string {
             "app" => Ok(Self::Application),
+            "apply" => Ok(Self::Apply),
+            "audit" => Ok(Self::Audit),
+            "config" => Ok(Self::Configuration),
             "help" => Ok(Self::Help),
+            "secret" => Ok(Self::Secret),
             "status" => Ok(Self::Status),
             other => Err(other.to_string()),
         }
@@ -1,4 +1,7 @@
-use sail_core::control::{Message, Reply, Request, Response};
+use sail_core::{
+    control::{Message, Reply, Request, Response},
+    protocol::Hello,
+};
 use std::{
     io::{self, BufRead, BufReader, Lines, Write},
     os::unix::net::UnixStream,
@@ -8,6 +11,8 @@ use std::{
 pub struct Controller {
     reader: Lines<BufReader<UnixStream>>,
     writer: UnixStream,
+    /// What the daemon said it supports in the handshake.
+    daemon: Hello,
 }
 
 impl Controller {
@@ -15,18 +20,62 @@ impl Controller {
         let stream =
             UnixStream::connect(socket_path).map_err(|e| Error::ConnectionFailure(e.kind()))?;
 
-        Ok(Self {
-            reader: BufReader::new(
-                stream
-                    .try_clone()
-                    .map_err(|e| Error::ConnectionFailure(e.kind()))?,
+        let mut reader = BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|e| Error::ConnectionFailure(e.kind()))?,
+        )
+        .lines();
+        let mut writer = stream;
+
+        let ours = Hello::new("sail", env!("CARGO_PKG_VERSION"));
+
+        writer
+            .write_all(
+                format!(
+                    "{}\n",
+                    serde_json::to_string(&ours).expect("hello serialization should succeed")
+                )
+                .as_bytes(),
             )
-            .lines(),
-            writer: stream,
+            .map_err(|e| Error::ConnectionFailure(e.kind()))?;
+
+        // Versions of `saild` from before the handshake fail to read the hello and hang up.
+        let daemon = match reader.next() {
+            Some(line) => {
+                let line = line.map_err(|e| Error::ConnectionFailure(e.kind()))?;
+                serde_json::from_str::<Hello>(&line).ok()
+            }
+            None => None,
+        }
+        .ok_or_else(|| {
+            Error::Incompatible(format!(
+                "saild is too old for sail {}, upgrade saild",
+                ours.version
+            ))
+        })?;
+
+        ours.negotiate(&daemon)
+            .map_err(|e| Error::Incompatible(e.to_string()))?;
+
+        Ok(Self {
+            reader,
+            writer,
+            daemon,
         })
     }
 
     pub fn request(&mut self, request: Request) -> Response {
+        if !self.daemon.supports(&request) {
+            return Response::Error {
+                message: format!(
+                    "saild {} does not support `{}`, upgrade saild",
+                    self.daemon.version,
+                    request.name()
+                ),
+            };
+        }
+
         let message = Message {
             id: rand::random(),
             request,
@@ -42,14 +91,23 @@ impl Controller {
             )
             .expect("writing to the stream should succeed");
 
-        let reply: Reply = serde_json::from_str(
-            &self
-                .reader
-                .next()
-                .expect("reading from the stream should succeed")
-                .expect("there should be a response"),
-        )
-        .expect("reply deserialization should succeed");
+        let line = self
+            .reader
+            .next()
+            .expect("reading from the stream should succeed")
+            .expect("there should be a response");
+
+        let reply: Reply = match serde_json::from_str(&line) {
+            Ok(reply) => reply,
+            Err(e) => {
+                return Response::Error {
+                    message: format!(
+                        "cannot read the reply of saild {}: {e}",
+                        self.daemon.version
+                    ),
+                }
+            }
+        };
 
         if reply.regarding != message.id {
             panic!("Reply ID did not match!")
@@ -62,4 +120,6 @@ impl Controller {
 #[derive(Debug)]
 pub enum Error {
     ConnectionFailure(io::ErrorKind),
+    /// `sail` and `saild` do not speak a common version of the control protocol.
+    Incompatible(String),
 }
@@ -3,6 +3,8 @@ use super::controller;
 #[derive(Debug)]
 pub enum Failure {
     ControllerError(controller::Error),
+    InvalidConfiguration(usize),
+    InvalidFile(String),
     MissingCommand,
     UnknownCommand(String),
 }
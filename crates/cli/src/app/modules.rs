@@ -1,7 +1,15 @@
 mod application;
+mod apply;
+mod audit;
+mod configuration;
 mod help;
+mod secret;
 mod status;
 
-pub use application::application;
+pub use application::{application, print_upload_keys};
+pub use apply::apply;
+pub use audit::audit;
+pub use configuration::configuration;
 pub use help::help;
+pub use secret::secret;
 pub use status::status;
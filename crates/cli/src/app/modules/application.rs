@@ -3,6 +3,11 @@ use sail_core::{
     application::Application,
     control::{Request, Response},
 };
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    time::Duration,
+};
 
 pub fn application(
     controller: &mut Controller,
@@ -25,11 +30,72 @@ pub fn application(
             let response = controller.request(request);
 
             match response {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::UploadKey { key } => {
+                    println!("SUCCESS!");
+                    print_upload_key(&key);
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "rotate-secret" => {
+            let hostname = arguments.next().ok_or(Failure::MissingCommand)?;
+            let mut grace_period = Duration::ZERO;
+
+            while let Some(argument) = arguments.next() {
+                match argument.as_str() {
+                    "--grace-period" => {
+                        let value = arguments.next().ok_or(Failure::MissingCommand)?;
+                        grace_period = humantime::parse_duration(&value)
+                            .map_err(|_| Failure::UnknownCommand(value))?;
+                    }
+                    _ => return Err(Failure::UnknownCommand(argument)),
+                }
+            }
+
+            let request = Request::RotateUploadKey {
+                hostname,
+                grace_period: grace_period.as_secs(),
+            };
+
+            match controller.request(request) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::UploadKey { key } => {
+                    println!("SUCCESS!");
+                    print_upload_key(&key);
+
+                    if !grace_period.is_zero() {
+                        println!(
+                            "the previous key keeps working for {}",
+                            humantime::format_duration(grace_period)
+                        );
+                    }
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "verify-secret" => {
+            let hostname = arguments.next().ok_or(Failure::MissingCommand)?;
+
+            // Read from standard input, so the key doesn't end up in the shell history.
+            let mut key = String::new();
+            io::stdin()
+                .read_to_string(&mut key)
+                .map_err(|e| Failure::InvalidFile(format!("failed to read standard input: {e}")))?;
+
+            match controller.request(Request::VerifyUploadKey {
+                hostname,
+                key: key.trim().to_owned(),
+            }) {
                 Response::Error { message } => {
                     eprintln!("ERROR:  {message}")
                 }
                 Response::Success => {
-                    println!("SUCCESS!")
+                    println!("SUCCESS! the upload key is valid")
                 }
                 other => panic!("Unexpected response: {other:?}"),
             }
@@ -73,3 +139,20 @@ pub fn application(
 
     Ok(())
 }
+
+fn print_upload_key(key: &str) {
+    println!("upload key: {key}");
+    println!("store it now, it cannot be shown again");
+}
+
+/// Print the upload keys of applications that were created by a plan or an import.
+pub fn print_upload_keys(keys: &BTreeMap<String, String>) {
+    if keys.is_empty() {
+        return;
+    }
+
+    for (hostname, key) in keys.iter() {
+        println!("upload key of {hostname}: {key}");
+    }
+    println!("store them now, they cannot be shown again");
+}
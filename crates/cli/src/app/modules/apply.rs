@@ -0,0 +1,115 @@
+use super::print_upload_keys;
+use crate::app::{controller::Controller, Failure};
+use sail_core::{
+    application::Application,
+    control::{Request, Response},
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+/// The applications that should exist, as read from the file given to `sail apply`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Desired {
+    #[serde(default)]
+    applications: Vec<Application>,
+}
+
+pub fn apply(
+    connect: impl FnOnce() -> Result<Controller, Failure>,
+    mut arguments: impl Iterator<Item = String>,
+) -> Result<(), Failure> {
+    let mut file = None;
+    let mut dry_run = false;
+    let mut prune = false;
+    let mut confirmed = false;
+
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "-f" | "--file" => file = Some(arguments.next().ok_or(Failure::MissingCommand)?),
+            "--dry-run" => dry_run = true,
+            "--prune" => prune = true,
+            "-y" | "--yes" => confirmed = true,
+            _ => return Err(Failure::UnknownCommand(argument)),
+        }
+    }
+
+    let file = file.ok_or(Failure::MissingCommand)?;
+    let content = fs::read_to_string(&file)
+        .map_err(|e| Failure::InvalidFile(format!("failed to read `{file}`: {e}")))?;
+    let desired: Desired = toml::from_str(&content)
+        .map_err(|e| Failure::InvalidFile(format!("failed to parse `{file}`: {e}")))?;
+
+    let mut controller = connect()?;
+
+    let plan = match controller.request(Request::PlanApplications {
+        applications: desired.applications,
+        prune,
+    }) {
+        Response::Error { message } => {
+            eprintln!("ERROR:  {message}");
+            return Ok(());
+        }
+        Response::Plan { plan, diagnostics } => {
+            for diagnostic in diagnostics.iter() {
+                println!("{diagnostic}");
+            }
+
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+            if errors > 0 {
+                return Err(Failure::InvalidConfiguration(errors));
+            }
+
+            plan
+        }
+        other => panic!("Unexpected response: {other:?}"),
+    };
+
+    if plan.is_empty() {
+        println!("no changes");
+        return Ok(());
+    }
+
+    for action in plan.actions.iter() {
+        println!("{action}");
+    }
+    println!("plan: {plan}");
+
+    if dry_run {
+        return Ok(());
+    }
+
+    if !confirmed && !confirm() {
+        println!("not applied");
+        return Ok(());
+    }
+
+    match controller.request(Request::ApplyPlan { plan }) {
+        Response::Error { message } => {
+            eprintln!("ERROR:  {message}")
+        }
+        Response::Applied { keys, .. } => {
+            println!("SUCCESS!");
+            print_upload_keys(&keys);
+        }
+        other => panic!("Unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
+
+/// Ask whether to apply the plan, anything but `y` or `yes` declines.
+fn confirm() -> bool {
+    print!("apply this plan? [y/N] ");
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
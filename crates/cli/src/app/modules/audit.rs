@@ -0,0 +1,89 @@
+use crate::app::{controller::Controller, Failure};
+use sail_core::{
+    audit::{AuditFilter, Outcome},
+    control::{Request, Response},
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn audit(
+    controller: &mut Controller,
+    mut arguments: impl Iterator<Item = String>,
+) -> Result<(), Failure> {
+    let mut filter = AuditFilter::default();
+
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--user" => filter.user = Some(arguments.next().ok_or(Failure::MissingCommand)?),
+            "--request" => filter.request = Some(arguments.next().ok_or(Failure::MissingCommand)?),
+            "--since" => {
+                let value = arguments.next().ok_or(Failure::MissingCommand)?;
+                let since = humantime::parse_duration(&value)
+                    .map(|ago| SystemTime::now() - ago)
+                    .or_else(|_| humantime::parse_rfc3339_weak(&value))
+                    .map_err(|_| Failure::UnknownCommand(value))?;
+
+                filter.since = Some(
+                    since
+                        .duration_since(UNIX_EPOCH)
+                        .map(|duration| duration.as_secs())
+                        .unwrap_or_default(),
+                );
+            }
+            "--failed" => filter.failed = true,
+            "-n" | "--limit" => {
+                let value = arguments.next().ok_or(Failure::MissingCommand)?;
+                filter.limit = Some(value.parse().map_err(|_| Failure::UnknownCommand(value))?);
+            }
+            _ => return Err(Failure::UnknownCommand(argument)),
+        }
+    }
+
+    match controller.request(Request::GetAuditLog { filter }) {
+        Response::Error { message } => {
+            eprintln!("ERROR:  {message}")
+        }
+        Response::AuditLog { entries } => {
+            for entry in entries.iter() {
+                let time = UNIX_EPOCH + Duration::from_secs(entry.time);
+
+                let caller = match &entry.caller {
+                    Some(caller) => {
+                        let ids = format!(
+                            "uid {}, gid {}, pid {}",
+                            caller.uid,
+                            caller.gid,
+                            caller
+                                .pid
+                                .map_or_else(|| "unknown".into(), |pid| pid.to_string())
+                        );
+
+                        match &caller.user {
+                            Some(user) => format!("{user} ({ids})"),
+                            None => ids,
+                        }
+                    }
+                    None => "unknown".into(),
+                };
+
+                let outcome = match &entry.outcome {
+                    Outcome::Success => "ok".to_owned(),
+                    Outcome::Failure { message } => format!("failed: {message}"),
+                };
+
+                println!(
+                    "{}  {caller}  {}  {outcome}",
+                    humantime::format_rfc3339_seconds(time),
+                    entry.request.name(),
+                );
+                println!(
+                    "    {}",
+                    serde_json::to_string(&entry.request)
+                        .expect("serialization of request should succeed")
+                );
+            }
+        }
+        other => panic!("Unexpected response: {other:?}"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use super::print_upload_keys;
+use crate::app::{controller::Controller, Failure};
+use sail_config::validation;
+use sail_core::{
+    control::{Request, Response},
+    diagnostic::Diagnostic,
+    export::{Format, ImportMode},
+};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+pub fn configuration(
+    connect: impl FnOnce() -> Result<Controller, Failure>,
+    mut arguments: impl Iterator<Item = String>,
+) -> Result<(), Failure> {
+    let subcommand = arguments.next().ok_or(Failure::MissingCommand)?;
+
+    match subcommand.as_str() {
+        "validate" => {
+            let diagnostics = match arguments.next() {
+                // A directory that is not live yet is validated locally.
+                Some(directory) => validation::load(Path::new(&directory)).diagnostics,
+                None => match connect()?.request(Request::ValidateConfiguration) {
+                    Response::Error { message } => {
+                        eprintln!("ERROR:  {message}");
+                        return Ok(());
+                    }
+                    Response::Diagnostics { diagnostics } => diagnostics,
+                    other => panic!("Unexpected response: {other:?}"),
+                },
+            };
+
+            report(&diagnostics)?;
+        }
+        "history" => match connect()?.request(Request::GetHistory) {
+            Response::Error { message } => {
+                eprintln!("ERROR:  {message}")
+            }
+            Response::History { entries } => {
+                for entry in entries.iter() {
+                    let time = UNIX_EPOCH + Duration::from_secs(entry.time);
+
+                    println!(
+                        "{}  {}  {}  {}",
+                        entry.number,
+                        humantime::format_rfc3339_seconds(time),
+                        entry.author,
+                        entry.description
+                    );
+
+                    for change in entry.changes.iter() {
+                        println!("    {change}");
+                    }
+                }
+            }
+            other => panic!("Unexpected response: {other:?}"),
+        },
+        "diff" => {
+            let from = number(arguments.next())?;
+            let to = number(arguments.next())?;
+
+            match connect()?.request(Request::DiffConfiguration { from, to }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Changes { changes } if changes.is_empty() => {
+                    println!("no changes")
+                }
+                Response::Changes { changes } => {
+                    for change in changes.iter() {
+                        println!("{change}");
+                    }
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "rollback" => {
+            let number = number(arguments.next())?;
+
+            match connect()?.request(Request::Rollback { number }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Success => {
+                    println!("SUCCESS!")
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "export" => {
+            let mut format = Format::default();
+            let mut secrets = false;
+            let mut output = None;
+
+            while let Some(argument) = arguments.next() {
+                match argument.as_str() {
+                    "--format" => format = self::format(arguments.next())?,
+                    "--secrets" => secrets = true,
+                    "-o" | "--output" => {
+                        output = Some(arguments.next().ok_or(Failure::MissingCommand)?)
+                    }
+                    _ => return Err(Failure::UnknownCommand(argument)),
+                }
+            }
+
+            match connect()?.request(Request::ExportConfiguration { format, secrets }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Document { document } => match output {
+                    Some(output) => fs::write(&output, document).map_err(|e| {
+                        Failure::InvalidFile(format!("failed to write `{output}`: {e}"))
+                    })?,
+                    None => print!("{document}"),
+                },
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "import" => {
+            let file = arguments.next().ok_or(Failure::MissingCommand)?;
+            let mut format = None;
+            let mut mode = ImportMode::default();
+
+            while let Some(argument) = arguments.next() {
+                match argument.as_str() {
+                    "--format" => format = Some(self::format(arguments.next())?),
+                    "--replace" => mode = ImportMode::Replace,
+                    "--merge" => mode = ImportMode::Merge,
+                    _ => return Err(Failure::UnknownCommand(argument)),
+                }
+            }
+
+            let format = format.unwrap_or(if file.ends_with(".json") {
+                Format::Json
+            } else {
+                Format::Toml
+            });
+            let document = fs::read_to_string(&file)
+                .map_err(|e| Failure::InvalidFile(format!("failed to read `{file}`: {e}")))?;
+
+            match connect()?.request(Request::ImportConfiguration {
+                document,
+                format,
+                mode,
+            }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Applied { changes, .. } if changes.is_empty() => {
+                    println!("no changes")
+                }
+                Response::Applied { changes, keys } => {
+                    for change in changes.iter() {
+                        println!("{change}");
+                    }
+                    print_upload_keys(&keys);
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        _ => return Err(Failure::UnknownCommand(subcommand)),
+    }
+
+    Ok(())
+}
+
+/// Parse the number of a configuration in the history.
+fn number(argument: Option<String>) -> Result<u64, Failure> {
+    let argument = argument.ok_or(Failure::MissingCommand)?;
+
+    argument
+        .parse()
+        .map_err(|_| Failure::UnknownCommand(argument))
+}
+
+/// Parse the format of an exported configuration.
+fn format(argument: Option<String>) -> Result<Format, Failure> {
+    argument
+        .ok_or(Failure::MissingCommand)?
+        .parse()
+        .map_err(Failure::UnknownCommand)
+}
+
+fn report(diagnostics: &[Diagnostic]) -> Result<(), Failure> {
+    for diagnostic in diagnostics {
+        println!("{diagnostic}");
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+
+    if errors > 0 {
+        return Err(Failure::InvalidConfiguration(errors));
+    }
+
+    println!(
+        "configuration is valid ({} warnings)",
+        diagnostics.len() - errors
+    );
+
+    Ok(())
+}
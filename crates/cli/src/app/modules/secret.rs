@@ -0,0 +1,84 @@
+use crate::app::{controller::Controller, Failure};
+use sail_core::control::{Request, Response};
+use std::io::{self, Read};
+
+pub fn secret(
+    controller: &mut Controller,
+    mut arguments: impl Iterator<Item = String>,
+) -> Result<(), Failure> {
+    let subcommand = arguments.next().ok_or(Failure::MissingCommand)?;
+    let hostname = arguments.next().ok_or(Failure::MissingCommand)?;
+
+    match subcommand.as_str() {
+        "set" => {
+            let name = arguments.next().ok_or(Failure::MissingCommand)?;
+
+            // Without a value on the command line, where it would end up in the shell history,
+            // it is read from standard input.
+            let value = match arguments.next() {
+                Some(value) => value,
+                None => {
+                    let mut value = String::new();
+                    io::stdin().read_to_string(&mut value).map_err(|e| {
+                        Failure::InvalidFile(format!("failed to read standard input: {e}"))
+                    })?;
+                    value.trim_end_matches(['\n', '\r']).to_owned()
+                }
+            };
+
+            match controller.request(Request::SetSecret {
+                hostname,
+                name,
+                value,
+            }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Success => {
+                    println!("SUCCESS!")
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "get" => {
+            let name = arguments.next().ok_or(Failure::MissingCommand)?;
+
+            match controller.request(Request::GetSecret { hostname, name }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Secret { value } => {
+                    println!("{value}")
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        "list" => match controller.request(Request::ListSecrets { hostname }) {
+            Response::Error { message } => {
+                eprintln!("ERROR:  {message}")
+            }
+            Response::Secrets { names } => {
+                for name in names.iter() {
+                    println!("{name}");
+                }
+            }
+            other => panic!("Unexpected response: {other:?}"),
+        },
+        "delete" => {
+            let name = arguments.next().ok_or(Failure::MissingCommand)?;
+
+            match controller.request(Request::DeleteSecret { hostname, name }) {
+                Response::Error { message } => {
+                    eprintln!("ERROR:  {message}")
+                }
+                Response::Success => {
+                    println!("SUCCESS!")
+                }
+                other => panic!("Unexpected response: {other:?}"),
+            }
+        }
+        _ => return Err(Failure::UnknownCommand(subcommand)),
+    }
+
+    Ok(())
+}
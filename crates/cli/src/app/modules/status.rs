@@ -1,5 +1,33 @@
 use crate::app::controller::Controller;
+use sail_core::control::{Request, Response};
 
-pub fn status(_controller: &mut Controller) {
-    println!("TODO: get status")
+pub fn status(controller: &mut Controller) {
+    match controller.request(Request::Status) {
+        Response::Error { message } => {
+            eprintln!("ERROR:  {message}")
+        }
+        Response::Status {
+            port,
+            applications,
+            problems,
+        } => {
+            println!("port: {port}");
+
+            println!("applications: {}", applications.len());
+            for application in applications.iter() {
+                println!("  {} -> {}", application.hostname, application.address);
+            }
+
+            if problems.iter().any(|p| p.is_error()) {
+                println!("configuration: degraded, files with errors were skipped");
+            } else {
+                println!("configuration: ok");
+            }
+
+            for problem in problems.iter() {
+                println!("  {problem}");
+            }
+        }
+        other => panic!("Unexpected response: {other:?}"),
+    }
 }
@@ -18,7 +18,16 @@ fn main() -> impl Termination {
                 controller::Error::ConnectionFailure(io_error_kind) => {
                     eprintln!("ERROR: controller failure: {:?}", io_error_kind)
                 }
+                controller::Error::Incompatible(message) => {
+                    eprintln!("ERROR: {message}")
+                }
             },
+            Failure::InvalidConfiguration(errors) => {
+                eprintln!("ERROR: configuration has {errors} errors")
+            }
+            Failure::InvalidFile(message) => {
+                eprintln!("ERROR: {message}")
+            }
             Failure::MissingCommand => {
                 eprintln!("ERROR: missing command")
             }
@@ -0,0 +1,127 @@
+//! Compares routing through the compiled `RoutingTable`, published through an atomic pointer,
+//! with the previous approach of locking a `Mutex` and scanning all applications, from one and
+//! from several threads at once.
+
+use arc_swap::ArcSwap;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sail_config::{CoreConfiguration, CurrentConfiguration, RoutingTable};
+use sail_core::application::Application;
+use std::{
+    hint::black_box,
+    net::SocketAddr,
+    sync::{Arc, Barrier, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const APPLICATIONS: usize = 500;
+const THREADS: [usize; 3] = [1, 4, 16];
+
+fn configuration() -> CurrentConfiguration {
+    let applications = (0..APPLICATIONS)
+        .map(|i| Application {
+            hostname: match i % 10 {
+                0 => format!("*.tenant-{i}.example.com"),
+                _ => format!("app-{i}.example.com"),
+            },
+            address: SocketAddr::from(([127, 0, 0, 1], 10000 + i as u16)),
+        })
+        .collect();
+
+    CurrentConfiguration {
+        core: CoreConfiguration::default(),
+        applications,
+    }
+}
+
+fn hosts() -> Vec<String> {
+    (0..APPLICATIONS)
+        .map(|i| match i % 10 {
+            0 => format!("www.tenant-{i}.example.com"),
+            _ => format!("app-{i}.example.com:4250"),
+        })
+        .collect()
+}
+
+/// Run `lookup` for every host on `threads` threads at once, returning the time taken by the
+/// slowest thread.
+fn concurrently<F>(threads: usize, iterations: u64, lookup: F) -> Duration
+where
+    F: Fn(&str) -> Option<SocketAddr> + Send + Sync + 'static,
+{
+    let lookup = Arc::new(lookup);
+    let hosts = Arc::new(hosts());
+    let barrier = Arc::new(Barrier::new(threads));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let (lookup, hosts, barrier) = (lookup.clone(), hosts.clone(), barrier.clone());
+
+            thread::spawn(move || {
+                barrier.wait();
+                let start = Instant::now();
+
+                for i in 0..iterations {
+                    let host = &hosts[i as usize % hosts.len()];
+                    black_box(lookup(black_box(host)));
+                }
+
+                start.elapsed()
+            })
+        })
+        .collect();
+
+    handles
+        .into_iter()
+        .map(|handle| handle.join().expect("benchmark thread should not panic"))
+        .max()
+        .unwrap_or_default()
+}
+
+fn routing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("routing");
+
+    for threads in THREADS {
+        let config = Arc::new(Mutex::new(Arc::new(configuration())));
+        group.bench_with_input(
+            BenchmarkId::new("mutex_scan", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iterations| {
+                    let config = config.clone();
+
+                    concurrently(threads, iterations, move |host| {
+                        let host = host.split(':').next().unwrap_or(host);
+                        let config = Arc::clone(&config.lock().unwrap());
+
+                        config
+                            .applications
+                            .iter()
+                            .find(|app| app.hostname == host)
+                            .map(|app| app.address)
+                    })
+                })
+            },
+        );
+
+        let routes = Arc::new(ArcSwap::from_pointee(RoutingTable::new(&configuration())));
+        group.bench_with_input(
+            BenchmarkId::new("routing_table", threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iterations| {
+                    let routes = routes.clone();
+
+                    concurrently(threads, iterations, move |host| {
+                        routes.load().route(host).map(|route| route.address)
+                    })
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, routing);
+criterion_main!(benches);
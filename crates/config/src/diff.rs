@@ -0,0 +1,8 @@
+use crate::CurrentConfiguration;
+
+impl CurrentConfiguration {
+    /// Describe the differences from this configuration to `new`, one line per change.
+    pub fn changes(&self, new: &CurrentConfiguration) -> Vec<String> {
+        self.events(new).iter().map(ToString::to_string).collect()
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{AccessConfiguration, CoreConfiguration, CurrentConfiguration, ListenerConfiguration};
+use core::fmt::{self, Display};
+use sail_core::application::Application;
+use std::sync::Arc;
+
+/// Number of updates a subscriber can fall behind before it misses some, see
+/// [`Configurable::subscribe`](crate::Configurable::subscribe).
+pub const UPDATES_CAPACITY: usize = 64;
+
+/// A configuration that was swapped in, with what changed.
+#[derive(Debug)]
+pub struct Update {
+    pub configuration: Arc<CurrentConfiguration>,
+    pub events: Vec<Event>,
+}
+
+/// A single change to the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The listeners the proxy serves on changed, including the default listener when `port`
+    /// changed.
+    Listeners {
+        old: Vec<ListenerConfiguration>,
+        new: Vec<ListenerConfiguration>,
+    },
+    /// `port` changed while `listeners` are configured, so it is not used.
+    Port {
+        old: u16,
+        new: u16,
+    },
+    MetricsPort {
+        old: Option<u16>,
+        new: Option<u16>,
+    },
+    Access {
+        old: Option<AccessConfiguration>,
+        new: Option<AccessConfiguration>,
+    },
+    ApplicationAdded(Application),
+    ApplicationChanged {
+        old: Application,
+        new: Application,
+    },
+    ApplicationRemoved(Application),
+}
+
+impl CurrentConfiguration {
+    /// The events that turn this configuration into `new`, which cover every difference.
+    pub fn events(&self, new: &CurrentConfiguration) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        // Destructured, so a new field cannot be missed.
+        let CoreConfiguration {
+            port,
+            listeners: _,
+            metrics_port,
+            access,
+        } = &self.core;
+
+        let (old_listeners, new_listeners) = (self.core.listeners(), new.core.listeners());
+        if old_listeners != new_listeners {
+            events.push(Event::Listeners {
+                old: old_listeners,
+                new: new_listeners,
+            });
+        } else if *port != new.core.port {
+            events.push(Event::Port {
+                old: *port,
+                new: new.core.port,
+            });
+        }
+
+        if *metrics_port != new.core.metrics_port {
+            events.push(Event::MetricsPort {
+                old: *metrics_port,
+                new: new.core.metrics_port,
+            });
+        }
+
+        if *access != new.core.access {
+            events.push(Event::Access {
+                old: access.clone(),
+                new: new.core.access.clone(),
+            });
+        }
+
+        for application in new.applications.iter() {
+            match self
+                .applications
+                .iter()
+                .find(|a| a.hostname == application.hostname)
+            {
+                None => events.push(Event::ApplicationAdded(application.clone())),
+                Some(previous) if previous != application => {
+                    events.push(Event::ApplicationChanged {
+                        old: previous.clone(),
+                        new: application.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        for application in self.applications.iter() {
+            if !new
+                .applications
+                .iter()
+                .any(|a| a.hostname == application.hostname)
+            {
+                events.push(Event::ApplicationRemoved(application.clone()));
+            }
+        }
+
+        events
+    }
+}
+
+/// Describes the change in a line for logs and the history. Values of the core configuration
+/// can be resolved references, so only addresses and ports are written.
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Listeners { old, new } => write!(
+                f,
+                "listeners: {} -> {}",
+                describe_listeners(old),
+                describe_listeners(new)
+            ),
+            Event::Port { old, new } => write!(f, "port (not used with listeners): {old} -> {new}"),
+            Event::MetricsPort { old, new } => write!(
+                f,
+                "metrics port: {} -> {}",
+                describe_port(*old),
+                describe_port(*new)
+            ),
+            Event::Access { .. } => write!(f, "changed access"),
+            Event::ApplicationAdded(application) => write!(
+                f,
+                "added application {} -> {}",
+                application.hostname, application.address
+            ),
+            Event::ApplicationChanged { old, new } => write!(
+                f,
+                "changed application {}: {} -> {}",
+                new.hostname, old.address, new.address
+            ),
+            Event::ApplicationRemoved(application) => {
+                write!(f, "removed application {}", application.hostname)
+            }
+        }
+    }
+}
+
+fn describe_listeners(listeners: &[ListenerConfiguration]) -> String {
+    let described: Vec<String> = listeners
+        .iter()
+        .map(|listener| {
+            let mut description = listener.socket_address().to_string();
+
+            if listener.ipv6_only {
+                description.push_str(" (IPv6 only)");
+            }
+            if listener.tls.is_some() {
+                description.push_str(" with TLS");
+            }
+            if listener.proxy_protocol.is_some() {
+                description.push_str(" with PROXY protocol");
+            }
+
+            description
+        })
+        .collect();
+
+    described.join(", ")
+}
+
+fn describe_port(port: Option<u16>) -> String {
+    match port {
+        Some(port) => port.to_string(),
+        None => "off".into(),
+    }
+}
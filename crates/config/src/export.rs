@@ -0,0 +1,149 @@
+use crate::{
+    interpolation::References,
+    schema::{self, Kind, SchemaError, SCHEMA_VERSION, VERSION_KEY},
+    CoreConfiguration, CurrentConfiguration,
+};
+use core::fmt::{self, Display};
+use sail_core::{application::Application, export::Format};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, path::PathBuf};
+use toml::{Table, Value};
+
+/// The complete configuration as a single document, to move it to another machine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Document {
+    pub core: CoreConfiguration,
+    #[serde(default)]
+    pub applications: Vec<Application>,
+    /// Files the configuration refers to, like TLS certificates and keys, when exported with
+    /// secrets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<EmbeddedFile>,
+    /// The secrets of the applications, when exported with secrets.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: Secrets,
+    /// Values that were written as references, which are exported as references.
+    #[serde(skip)]
+    pub references: References,
+}
+
+/// Values of secrets by name, by hostname of the application.
+pub type Secrets = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl Document {
+    pub fn new(
+        configuration: &CurrentConfiguration,
+        references: References,
+        files: Vec<EmbeddedFile>,
+        secrets: Secrets,
+    ) -> Self {
+        Self {
+            core: configuration.core.clone(),
+            applications: configuration.applications.clone(),
+            files,
+            secrets,
+            references,
+        }
+    }
+
+    pub fn configuration(&self) -> CurrentConfiguration {
+        CurrentConfiguration {
+            core: self.core.clone(),
+            applications: self.applications.clone(),
+        }
+    }
+
+    /// Read a document, upgrading it to the current schema version first if it is older and
+    /// resolving the references in it.
+    pub fn parse(content: &str, format: Format) -> Result<Self, DocumentError> {
+        let mut table: Table = match format {
+            Format::Toml => {
+                toml::from_str(content).map_err(|e| DocumentError::Schema(SchemaError::Parse(e)))?
+            }
+            Format::Json => serde_json::from_str(content).map_err(DocumentError::Json)?,
+        };
+
+        let version = schema::version(&table).map_err(DocumentError::Schema)?;
+        table.remove(VERSION_KEY);
+
+        if let Some(Value::Table(core)) = table.get_mut("core") {
+            schema::migrate(core, version, Kind::Core).map_err(DocumentError::Schema)?;
+        }
+
+        if let Some(Value::Array(applications)) = table.get_mut("applications") {
+            for application in applications.iter_mut() {
+                if let Value::Table(application) = application {
+                    schema::migrate(application, version, Kind::Application)
+                        .map_err(DocumentError::Schema)?;
+                }
+            }
+        }
+
+        let references = References::interpolate_configuration(&mut table)
+            .map_err(|e| DocumentError::Schema(SchemaError::Interpolation(e)))?;
+
+        let document: Self = Value::Table(table)
+            .try_into()
+            .map_err(|e| DocumentError::Schema(SchemaError::Parse(e)))?;
+
+        Ok(Self {
+            references,
+            ..document
+        })
+    }
+
+    /// Write the document in the current schema version.
+    pub fn write(&self, format: Format) -> String {
+        let mut table = Table::try_from(self).expect("internal config should be serializable");
+        self.references.restore_configuration(&mut table);
+
+        match format {
+            Format::Toml => schema::write(&table),
+            Format::Json => {
+                let mut value =
+                    serde_json::to_value(&table).expect("internal config should be serializable");
+                value[VERSION_KEY] = SCHEMA_VERSION.into();
+
+                serde_json::to_string_pretty(&value)
+                    .expect("internal config should be serializable")
+            }
+        }
+    }
+}
+
+impl CoreConfiguration {
+    /// The TLS certificates and keys of the listeners, which are embedded in an export with
+    /// secrets.
+    pub fn tls_files(&self) -> Vec<PathBuf> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.tls.as_ref())
+            .flat_map(|tls| [tls.certificate.clone(), tls.key.clone()])
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum DocumentError {
+    Json(serde_json::Error),
+    Schema(SchemaError),
+}
+
+impl Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::Json(e) => write!(f, "{e}"),
+            DocumentError::Schema(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for DocumentError {}
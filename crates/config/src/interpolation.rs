@@ -0,0 +1,477 @@
+use crate::{CoreConfiguration, CurrentConfiguration};
+use core::fmt::{self, Display};
+use sail_core::application::Application;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+/// Environment variable set by systemd to the directory with the credentials of the service,
+/// see `LoadCredential=` in `systemd.exec(5)`.
+pub const CREDENTIALS_DIRECTORY_VARIABLE: &str = "CREDENTIALS_DIRECTORY";
+
+/// A step from a table to one of its values.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A string value in a configuration file that contains references, like
+/// `token = "${env:TOKEN}"`, with the value it resolved to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    path: Vec<Segment>,
+    raw: String,
+    value: String,
+}
+
+/// Replace references in the string values of `table` with what they refer to:
+///
+/// - `${env:NAME}`, the environment variable `NAME`,
+/// - `${file:/path}`, the contents of a file,
+/// - `${credential:name}`, the contents of a systemd credential.
+///
+/// `$${` is written as `${`. Returns the values that changed, so they can be written back as
+/// references with [`restore`].
+pub fn interpolate(table: &mut Table) -> Result<Vec<Reference>, InterpolationError> {
+    interpolate_with(table, &|name| env::var_os(name))
+}
+
+/// Looks up an environment variable.
+type Environment<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
+/// Like [`interpolate`], with the environment variables of `environment`.
+fn interpolate_with(
+    table: &mut Table,
+    environment: Environment,
+) -> Result<Vec<Reference>, InterpolationError> {
+    let mut references = Vec::new();
+    let mut path = Vec::new();
+
+    for (key, value) in table.iter_mut() {
+        path.push(Segment::Key(key.clone()));
+        walk(value, &mut path, &mut references, environment)?;
+        path.pop();
+    }
+
+    Ok(references)
+}
+
+/// Put `references` back into `table`, wherever the value is still what the reference resolved
+/// to.
+pub fn restore(table: &mut Table, references: &[Reference]) {
+    for reference in references {
+        if let Some(Value::String(value)) = get_mut(table, &reference.path) {
+            if *value == reference.value {
+                value.clone_from(&reference.raw);
+            }
+        }
+    }
+}
+
+fn walk(
+    value: &mut Value,
+    path: &mut Vec<Segment>,
+    references: &mut Vec<Reference>,
+    environment: Environment,
+) -> Result<(), InterpolationError> {
+    match value {
+        Value::String(string) if string.contains('$') => {
+            let resolved = resolve(string, environment).map_err(|reason| InterpolationError {
+                field: field(path),
+                reason,
+            })?;
+
+            if resolved != *string {
+                references.push(Reference {
+                    path: path.clone(),
+                    raw: string.clone(),
+                    value: resolved.clone(),
+                });
+                *string = resolved;
+            }
+        }
+        Value::Array(array) => {
+            for (index, value) in array.iter_mut().enumerate() {
+                path.push(Segment::Index(index));
+                walk(value, path, references, environment)?;
+                path.pop();
+            }
+        }
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                path.push(Segment::Key(key.clone()));
+                walk(value, path, references, environment)?;
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn get_mut<'a>(table: &'a mut Table, path: &[Segment]) -> Option<&'a mut Value> {
+    let (Segment::Key(key), rest) = path.split_first()? else {
+        return None;
+    };
+
+    rest.iter().try_fold(table.get_mut(key)?, |value, segment| {
+        match (value, segment) {
+            (Value::Table(table), Segment::Key(key)) => table.get_mut(key),
+            (Value::Array(array), Segment::Index(index)) => array.get_mut(*index),
+            _ => None,
+        }
+    })
+}
+
+/// Describe a path like `listeners[0].tls.key`.
+fn field(path: &[Segment]) -> String {
+    let mut field = String::new();
+
+    for segment in path {
+        match segment {
+            Segment::Key(key) if field.is_empty() => field.push_str(key),
+            Segment::Key(key) => {
+                field.push('.');
+                field.push_str(key);
+            }
+            Segment::Index(index) => field.push_str(&format!("[{index}]")),
+        }
+    }
+
+    field
+}
+
+fn resolve(raw: &str, environment: Environment) -> Result<String, String> {
+    let mut resolved = String::new();
+    let mut rest = raw;
+
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            resolved.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .ok_or("unterminated reference, `${` without `}`")?;
+
+            resolved.push_str(&reference(&after[..end], environment)?);
+            rest = &after[end + 1..];
+        } else {
+            resolved.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    resolved.push_str(rest);
+
+    Ok(resolved)
+}
+
+fn reference(reference: &str, environment: Environment) -> Result<String, String> {
+    match reference.split_once(':') {
+        Some(("env", name)) => environment(name)
+            .ok_or_else(|| format!("environment variable `{name}` is not set"))?
+            .into_string()
+            .map_err(|_| format!("environment variable `{name}` is not valid UTF-8")),
+        Some(("file", path)) if Path::new(path).is_absolute() => read(Path::new(path)),
+        Some(("file", path)) => Err(format!("`{path}` is not an absolute path")),
+        Some(("credential", name)) if name.is_empty() || name.contains('/') => {
+            Err(format!("invalid credential name `{name}`"))
+        }
+        Some(("credential", name)) => {
+            let directory = environment(CREDENTIALS_DIRECTORY_VARIABLE).ok_or_else(|| {
+                format!("cannot read credential `{name}`, `${CREDENTIALS_DIRECTORY_VARIABLE}` is not set")
+            })?;
+
+            read(&PathBuf::from(directory).join(name))
+        }
+        _ => Err(format!(
+            "unknown reference `${{{reference}}}`, expected `${{env:...}}`, `${{file:...}}` or `${{credential:...}}`"
+        )),
+    }
+}
+
+/// Read a file referred to, without the trailing newline most editors add.
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|contents| contents.trim_end_matches(['\n', '\r']).to_owned())
+        .map_err(|e| format!("cannot read `{}`: {e}", path.display()))
+}
+
+/// The references in the configuration files, so they are saved as references instead of the
+/// values they resolved to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct References {
+    core: Vec<Reference>,
+    /// By hostname of the application.
+    applications: HashMap<String, Vec<Reference>>,
+}
+
+impl References {
+    pub fn set_core(&mut self, references: Vec<Reference>) {
+        self.core = references;
+    }
+
+    pub fn set_application(&mut self, hostname: &str, references: Vec<Reference>) {
+        if references.is_empty() {
+            self.applications.remove(hostname);
+        } else {
+            self.applications.insert(hostname.to_owned(), references);
+        }
+    }
+
+    /// Add the references of `other`, which take precedence.
+    pub fn merge(&mut self, other: References) {
+        if !other.core.is_empty() {
+            self.core = other.core;
+        }
+
+        self.applications.extend(other.applications);
+    }
+
+    /// The core configuration as it should be written to a file.
+    pub fn core_table(&self, core: &CoreConfiguration) -> Table {
+        let mut table = to_table(core);
+        restore(&mut table, &self.core);
+        table
+    }
+
+    /// An application as it should be written to a file.
+    pub fn application_table(&self, application: &Application) -> Table {
+        let mut table = to_table(application);
+
+        if let Some(references) = self.applications.get(&application.hostname) {
+            restore(&mut table, references);
+        }
+
+        table
+    }
+
+    /// Put the references back into a table with a `core` table and an `applications` array,
+    /// the layout of exported configurations and snapshots in the history.
+    pub fn restore_configuration(&self, table: &mut Table) {
+        if let Some(Value::Table(core)) = table.get_mut("core") {
+            restore(core, &self.core);
+        }
+
+        if let Some(Value::Array(applications)) = table.get_mut("applications") {
+            for application in applications.iter_mut() {
+                if let Value::Table(application) = application {
+                    let references = application
+                        .get("hostname")
+                        .and_then(Value::as_str)
+                        .and_then(|hostname| self.applications.get(hostname));
+
+                    if let Some(references) = references {
+                        restore(application, references);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resolve the references in a table with the layout of [`References::restore_configuration`].
+    pub fn interpolate_configuration(table: &mut Table) -> Result<Self, InterpolationError> {
+        let mut references = Self::default();
+
+        if let Some(Value::Table(core)) = table.get_mut("core") {
+            references.core = interpolate(core).map_err(|e| e.within("core"))?;
+        }
+
+        if let Some(Value::Array(applications)) = table.get_mut("applications") {
+            for (index, application) in applications.iter_mut().enumerate() {
+                if let Value::Table(application) = application {
+                    let found = interpolate(application)
+                        .map_err(|e| e.within(&format!("applications[{index}]")))?;
+
+                    if let Some(hostname) = application.get("hostname").and_then(Value::as_str) {
+                        references.set_application(hostname, found);
+                    }
+                }
+            }
+        }
+
+        Ok(references)
+    }
+
+    /// A configuration as a table with the layout of [`References::restore_configuration`].
+    pub fn configuration_table(&self, configuration: &CurrentConfiguration) -> Table {
+        let mut table = to_table(configuration);
+        self.restore_configuration(&mut table);
+        table
+    }
+}
+
+fn to_table<T>(value: &T) -> Table
+where
+    T: Serialize,
+{
+    Table::try_from(value).expect("internal config should be serializable")
+}
+
+#[derive(Debug)]
+pub struct InterpolationError {
+    /// Path of the value with the reference, like `listeners[0].tls.key`.
+    pub field: String,
+    pub reason: String,
+}
+
+impl InterpolationError {
+    fn within(self, parent: &str) -> Self {
+        Self {
+            field: format!("{parent}.{}", self.field),
+            reason: self.reason,
+        }
+    }
+}
+
+impl Display for InterpolationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot resolve `{}`: {}", self.field, self.reason)
+    }
+}
+
+impl Error for InterpolationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// An environment with only `variables`.
+    fn environment_of<'a>(variables: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |name| {
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.into())
+        }
+    }
+
+    #[test]
+    fn resolve_literals() {
+        let environment = environment_of(&[]);
+
+        assert_eq!(resolve("plain", &environment).unwrap(), "plain");
+        assert_eq!(resolve("costs $5", &environment).unwrap(), "costs $5");
+        assert_eq!(
+            resolve("$${env:HOME}", &environment).unwrap(),
+            "${env:HOME}"
+        );
+        assert_eq!(resolve("a$$${b", &environment).unwrap(), "a$${b");
+    }
+
+    #[test]
+    fn resolve_environment() {
+        let environment = environment_of(&[("TOKEN", "secret")]);
+
+        assert_eq!(resolve("${env:TOKEN}", &environment).unwrap(), "secret");
+        assert_eq!(
+            resolve("a-${env:TOKEN}-${env:TOKEN}-b", &environment).unwrap(),
+            "a-secret-secret-b"
+        );
+        assert!(resolve("${env:UNSET}", &environment).is_err());
+    }
+
+    #[test]
+    fn resolve_file() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("token");
+        fs::write(&path, "from a file\n").unwrap();
+
+        let environment = environment_of(&[]);
+
+        assert_eq!(
+            resolve(&format!("${{file:{}}}", path.display()), &environment).unwrap(),
+            "from a file"
+        );
+        assert!(resolve("${file:relative/path}", &environment).is_err());
+    }
+
+    #[test]
+    fn resolve_credential() {
+        let directory = TempDir::new().unwrap();
+        fs::write(directory.path().join("token"), "credential").unwrap();
+
+        let variables = [(
+            CREDENTIALS_DIRECTORY_VARIABLE,
+            directory.path().to_str().unwrap(),
+        )];
+        let environment = environment_of(&variables);
+
+        assert_eq!(
+            resolve("${credential:token}", &environment).unwrap(),
+            "credential"
+        );
+        assert!(resolve("${credential:missing}", &environment).is_err());
+        assert!(resolve("${credential:token}", &environment_of(&[])).is_err());
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let environment = environment_of(&[("NAME", "value")]);
+
+        assert!(resolve("${env:NAME", &environment).is_err());
+        assert!(resolve("${unknown:x}", &environment).is_err());
+        assert!(resolve("${nothing}", &environment).is_err());
+        assert!(resolve("${credential:../x}", &environment).is_err());
+        assert!(resolve("${credential:}", &environment).is_err());
+    }
+
+    #[test]
+    fn interpolate_and_restore() {
+        let mut table: Table = toml::from_str(
+            r#"
+            name = "${env:TOKEN}"
+            plain = "value"
+            list = ["x", "${env:TOKEN}"]
+            nested = { key = "${env:TOKEN}" }
+            "#,
+        )
+        .unwrap();
+        let original = table.clone();
+
+        let references =
+            interpolate_with(&mut table, &environment_of(&[("TOKEN", "token")])).unwrap();
+        assert_eq!(references.len(), 3);
+        assert_eq!(table["name"].as_str(), Some("token"));
+        assert_eq!(table["list"][1].as_str(), Some("token"));
+        assert_eq!(table["nested"]["key"].as_str(), Some("token"));
+
+        restore(&mut table, &references);
+        assert_eq!(table, original);
+    }
+
+    #[test]
+    fn restore_keeps_changed_values() {
+        let mut table: Table = toml::from_str(r#"key = "${env:TOKEN}""#).unwrap();
+        let references =
+            interpolate_with(&mut table, &environment_of(&[("TOKEN", "old")])).unwrap();
+
+        table.insert("key".into(), Value::String("new".into()));
+        restore(&mut table, &references);
+
+        assert_eq!(table["key"].as_str(), Some("new"));
+    }
+
+    #[test]
+    fn interpolate_reports_field() {
+        let mut table: Table =
+            toml::from_str(r#"listeners = [{ tls = { key = "${env:UNSET}" } }]"#).unwrap();
+
+        let error = interpolate_with(&mut table, &environment_of(&[])).unwrap_err();
+        assert_eq!(error.field, "listeners[0].tls.key");
+    }
+}
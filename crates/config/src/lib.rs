@@ -1,19 +1,217 @@
-use sail_core::application::Application;
+mod diff;
+pub mod events;
+pub mod export;
+pub mod interpolation;
+mod routing;
+pub mod schema;
+pub mod validation;
+
+pub use routing::{Route, RoutingTable, WILDCARD_PREFIX};
+
+use ipnet::IpNet;
+use sail_core::{access::Role, application::Application, diagnostic::Diagnostic};
 use serde::{Deserialize, Serialize};
-use std::{future::Future, sync::Arc};
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::sync::broadcast;
+
+pub const DEFAULT_PORT: u16 = 4250;
+pub const DEFAULT_LISTENER_NAME: &str = "http";
+
+/// Name of the core configuration file, inside the configuration directory.
+pub const CORE_FILE: &str = "configuration.toml";
+
+/// Name of the directory with one file per application, inside the configuration directory.
+pub const APPLICATIONS_DIRECTORY: &str = "applications";
 
 pub trait Configurable {
     fn get(&self) -> Arc<CurrentConfiguration>;
-    fn set(&self, new: CurrentConfiguration) -> impl Future<Output = ()>;
+    /// The routing table compiled from the current configuration.
+    fn routes(&self) -> Arc<RoutingTable>;
+    /// Problems found the last time the configuration was read from disk.
+    fn problems(&self) -> Arc<Vec<Diagnostic>>;
+    /// Store the configuration, recording `change` in the history, and replace the current one
+    /// with it. The current configuration is kept if storing fails.
+    fn set(
+        &self,
+        new: CurrentConfiguration,
+        change: Change,
+    ) -> impl Future<Output = io::Result<()>>;
+    /// Receive an [`Update`](events::Update) whenever a different configuration is swapped in,
+    /// whether it was set or reloaded. A subscriber that falls behind by more than
+    /// [`UPDATES_CAPACITY`](events::UPDATES_CAPACITY) updates is told it lagged, and should
+    /// catch up with [`Configurable::get`].
+    fn subscribe(&self) -> broadcast::Receiver<Arc<events::Update>>;
+}
+
+/// Who made a change to the configuration, and why.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub author: String,
+    pub description: String,
+}
+
+impl Change {
+    pub fn new(author: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            author: author.into(),
+            description: description.into(),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct CurrentConfiguration {
     pub core: CoreConfiguration,
     pub applications: Vec<Application>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct CoreConfiguration {
+    /// Port of the default listener on `127.0.0.1`, used when no `listeners` are configured.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfiguration>,
+    /// Port on the loopback interface where Prometheus metrics are served, if enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u16>,
+    /// Who may do what through the control socket. Without it, everyone who can connect to the
+    /// socket is an admin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessConfiguration>,
+}
+
+impl CoreConfiguration {
+    /// The listeners the proxy should serve on, falling back to the default listener.
+    pub fn listeners(&self) -> Vec<ListenerConfiguration> {
+        if self.listeners.is_empty() {
+            vec![ListenerConfiguration {
+                name: Some(DEFAULT_LISTENER_NAME.into()),
+                address: Ipv4Addr::LOCALHOST.into(),
+                port: self.port,
+                ipv6_only: false,
+                tls: None,
+                proxy_protocol: None,
+            }]
+        } else {
+            self.listeners.clone()
+        }
+    }
+}
+
+impl Default for CoreConfiguration {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            listeners: Vec::new(),
+            metrics_port: None,
+            access: None,
+        }
+    }
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ListenerConfiguration {
+    /// Name (`FileDescriptorName=`) of a socket passed by systemd. When systemd passes a socket
+    /// with this name, it is used instead of binding to `address` and `port`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub address: IpAddr,
     pub port: u16,
+    /// Only accept IPv6 connections on an IPv6 address. By default, listening on `::` also
+    /// accepts IPv4 connections (dual-stack).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ipv6_only: bool,
+    /// Terminate TLS on this listener.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfiguration>,
+    /// Accept PROXY protocol headers on this listener from these peers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolConfiguration>,
+}
+
+impl ListenerConfiguration {
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TlsConfiguration {
+    /// PEM file with the certificate chain.
+    pub certificate: PathBuf,
+    /// PEM file with the private key.
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AccessConfiguration {
+    #[serde(default, skip_serializing_if = "RoleMembers::is_empty")]
+    pub admin: RoleMembers,
+    #[serde(default, skip_serializing_if = "RoleMembers::is_empty")]
+    pub operator: RoleMembers,
+    #[serde(default, skip_serializing_if = "RoleMembers::is_empty")]
+    pub read_only: RoleMembers,
+}
+
+impl AccessConfiguration {
+    /// The highest role of a user with `uid` in `groups`, if they have any. Root is always an
+    /// admin.
+    pub fn role(&self, uid: u32, groups: &[String]) -> Option<Role> {
+        if uid == 0 {
+            return Some(Role::Admin);
+        }
+
+        [
+            (Role::Admin, &self.admin),
+            (Role::Operator, &self.operator),
+            (Role::ReadOnly, &self.read_only),
+        ]
+        .into_iter()
+        .find(|(_, members)| members.contains(uid, groups))
+        .map(|(role, _)| role)
+    }
+}
+
+/// The users who have a role, by uid or by group name.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RoleMembers {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl RoleMembers {
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.groups.is_empty()
+    }
+
+    fn contains(&self, uid: u32, groups: &[String]) -> bool {
+        self.uids.contains(&uid) || self.groups.iter().any(|group| groups.contains(group))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProxyProtocolConfiguration {
+    /// Address ranges of the load balancers that are allowed to send a PROXY protocol header.
+    /// Connections from other peers are served as-is, using the peer address.
+    pub trusted: Vec<IpNet>,
+}
+
+impl ProxyProtocolConfiguration {
+    pub fn trusts(&self, address: IpAddr) -> bool {
+        let address = address.to_canonical();
+        self.trusted.iter().any(|range| range.contains(&address))
+    }
 }
@@ -0,0 +1,111 @@
+use crate::CurrentConfiguration;
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Prefix of application hostnames that match every subdomain, like `*.example.com`.
+pub const WILDCARD_PREFIX: &str = "*.";
+
+/// Hostname lookup compiled from the applications of a configuration.
+///
+/// Exact hostnames are looked up in a hash map. Wildcard hostnames are stored in a trie keyed by
+/// the labels of the hostname from right to left, so the most specific wildcard wins.
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    exact: HashMap<String, Route>,
+    wildcards: Node,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    /// Route for subdomains of the hostname ending at this node.
+    route: Option<Route>,
+}
+
+/// The application a request is routed to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    /// Hostname of the application as configured, which may be a wildcard.
+    pub hostname: String,
+    pub address: SocketAddr,
+}
+
+impl RoutingTable {
+    pub fn new(config: &CurrentConfiguration) -> Self {
+        let mut table = Self::default();
+
+        for application in config.applications.iter() {
+            let hostname = normalize(&application.hostname);
+            let route = Route {
+                hostname: application.hostname.clone(),
+                address: application.address,
+            };
+
+            match hostname.strip_prefix(WILDCARD_PREFIX) {
+                Some(domain) => {
+                    let node = domain
+                        .rsplit('.')
+                        .fold(&mut table.wildcards, |node, label| {
+                            node.children.entry(label.to_owned()).or_default()
+                        });
+
+                    node.route.get_or_insert(route);
+                }
+                None => {
+                    table.exact.entry(hostname).or_insert(route);
+                }
+            }
+        }
+
+        table
+    }
+
+    /// Find the application serving `host`, the value of a `Host` header.
+    pub fn route(&self, host: &str) -> Option<&Route> {
+        let host = strip_port(host);
+
+        let exact = match host.bytes().any(|b| b.is_ascii_uppercase()) {
+            true => self.exact.get(&host.to_ascii_lowercase()),
+            false => self.exact.get(host),
+        };
+        if let Some(route) = exact {
+            return Some(route);
+        }
+
+        let host = host.to_ascii_lowercase();
+        let mut labels = host.rsplit('.').peekable();
+        let mut node = &self.wildcards;
+        let mut found = None;
+
+        while let Some(label) = labels.next() {
+            match node.children.get(label) {
+                Some(child) => node = child,
+                None => break,
+            }
+
+            // A wildcard only matches when there is at least one more label to the left.
+            if labels.peek().is_some() {
+                found = node.route.as_ref().or(found);
+            }
+        }
+
+        found
+    }
+}
+
+fn normalize(hostname: &str) -> String {
+    strip_port(hostname).to_ascii_lowercase()
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // Leave bare IPv6 addresses like `::1` alone.
+        Some((name, port))
+            if (!name.contains(':') || name.ends_with(']'))
+                && !port.is_empty()
+                && port.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
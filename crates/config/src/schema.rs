@@ -0,0 +1,168 @@
+use crate::interpolation::{self, InterpolationError, Reference};
+use core::fmt::{self, Display};
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+use toml::{Table, Value};
+
+/// Version of the format of the configuration files written by this version of Sail.
+///
+/// Bump this and add a [`Migration`] to [`MIGRATIONS`] whenever a change to
+/// `CoreConfiguration` or `Application` would stop older files from deserializing.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Key holding the schema version at the top of every configuration file. Files from before
+/// versioning don't have it and are version 0.
+pub const VERSION_KEY: &str = "version";
+
+/// The kinds of configuration files, which are migrated separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Core,
+    Application,
+}
+
+/// Upgrades files from version `from` to version `from + 1`.
+struct Migration {
+    from: u32,
+    core: fn(&mut Table) -> Result<(), String>,
+    application: fn(&mut Table) -> Result<(), String>,
+}
+
+/// Every migration, in order.
+const MIGRATIONS: &[Migration] = &[
+    // Version 1 introduced the `version` key itself, the format is otherwise unchanged.
+    Migration {
+        from: 0,
+        core: |_| Ok(()),
+        application: |_| Ok(()),
+    },
+];
+
+/// A value read from a configuration file.
+pub struct Versioned<T> {
+    pub value: T,
+    /// The version the file was written in, older than [`SCHEMA_VERSION`] if it was migrated.
+    pub version: u32,
+    /// Values that were written as references, see [`interpolation`].
+    pub references: Vec<Reference>,
+}
+
+impl<T> Versioned<T> {
+    pub fn migrated(&self) -> bool {
+        self.version < SCHEMA_VERSION
+    }
+}
+
+/// Read a configuration file, upgrading it to the current schema version first if it is older
+/// and resolving the references in it.
+pub fn read<T>(content: &str, kind: Kind) -> Result<Versioned<T>, SchemaError>
+where
+    T: DeserializeOwned,
+{
+    let mut table: Table = toml::from_str(content).map_err(SchemaError::Parse)?;
+
+    let version = version(&table)?;
+    migrate(&mut table, version, kind)?;
+
+    table.remove(VERSION_KEY);
+    let references = interpolation::interpolate(&mut table).map_err(SchemaError::Interpolation)?;
+
+    // Deserializing from the original text keeps the location of errors.
+    let value = if version == SCHEMA_VERSION && references.is_empty() {
+        toml::from_str(content).map_err(SchemaError::Parse)?
+    } else {
+        Value::Table(table).try_into().map_err(SchemaError::Parse)?
+    };
+
+    Ok(Versioned {
+        value,
+        version,
+        references,
+    })
+}
+
+/// The schema version of a parsed file, which must not be newer than [`SCHEMA_VERSION`].
+pub fn version(table: &Table) -> Result<u32, SchemaError> {
+    let version = match table.get(VERSION_KEY) {
+        None => 0,
+        Some(Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| SchemaError::InvalidVersion(version.to_string()))?
+        }
+        Some(other) => return Err(SchemaError::InvalidVersion(other.to_string())),
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(SchemaError::Newer(version));
+    }
+
+    Ok(version)
+}
+
+/// Upgrade `table` from `version` to [`SCHEMA_VERSION`].
+pub fn migrate(table: &mut Table, version: u32, kind: Kind) -> Result<(), SchemaError> {
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        let migrate = match kind {
+            Kind::Core => migration.core,
+            Kind::Application => migration.application,
+        };
+
+        migrate(table).map_err(|reason| SchemaError::Migration {
+            from: migration.from,
+            reason,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Write a configuration file in the current schema version.
+pub fn write<T>(value: &T) -> String
+where
+    T: Serialize,
+{
+    let body = toml::to_string_pretty(value).expect("internal config should be serializable");
+
+    format!("{VERSION_KEY} = {SCHEMA_VERSION}\n\n{body}")
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Parse(toml::de::Error),
+    InvalidVersion(String),
+    Newer(u32),
+    Migration { from: u32, reason: String },
+    Interpolation(InterpolationError),
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Parse(e) => write!(f, "{}", e.message()),
+            SchemaError::InvalidVersion(version) => {
+                write!(f, "invalid schema version `{version}`")
+            }
+            SchemaError::Newer(version) => write!(
+                f,
+                "schema version {version} is newer than {SCHEMA_VERSION}, the latest this version of Sail supports"
+            ),
+            SchemaError::Migration { from, reason } => write!(
+                f,
+                "upgrading from schema version {from} to {} failed: {reason}",
+                from + 1
+            ),
+            SchemaError::Interpolation(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+/// Make sure every version has a migration, so none is skipped silently.
+const _: () = {
+    let mut i = 0;
+    while i < MIGRATIONS.len() {
+        assert!(MIGRATIONS[i].from == i as u32);
+        i += 1;
+    }
+    assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize);
+};
@@ -0,0 +1,541 @@
+use crate::{
+    interpolation::References,
+    schema::{self, Kind, SchemaError, VERSION_KEY},
+    CoreConfiguration, CurrentConfiguration, ListenerConfiguration, APPLICATIONS_DIRECTORY,
+    CORE_FILE, WILDCARD_PREFIX,
+};
+use sail_core::{application::Application, diagnostic::Diagnostic};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+/// Maximum length of a hostname, see RFC 1035.
+const MAX_HOSTNAME_LENGTH: usize = 253;
+
+/// Maximum length of a single label of a hostname, see RFC 1035.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// A configuration read from a directory, with every problem found in it.
+pub struct Loaded {
+    pub configuration: CurrentConfiguration,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Files that were left out of the configuration because they could not be read or parsed.
+    pub skipped: Vec<PathBuf>,
+    /// Files in an older schema version that were upgraded while reading, with their version.
+    pub migrated: Vec<(PathBuf, u32)>,
+    /// Values that were written as references, to save them as references again.
+    pub references: References,
+}
+
+impl Loaded {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+/// Read the configuration in `root` and validate it. Files that cannot be read or parsed are
+/// reported and left out of the configuration.
+pub fn load(root: &Path) -> Loaded {
+    let mut diagnostics = Vec::new();
+    let mut skipped = Vec::new();
+    let mut migrated = Vec::new();
+    let mut references = References::default();
+
+    match fs::metadata(root) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => diagnostics.push(Diagnostic::error(format!(
+            "`{}` is not a directory",
+            root.display()
+        ))),
+        Err(e) => diagnostics.push(Diagnostic::error(format!(
+            "cannot read `{}`: {e}",
+            root.display()
+        ))),
+    }
+
+    let core = match fs::read_to_string(root.join(CORE_FILE)) {
+        Ok(content) => match schema::read(&content, Kind::Core) {
+            Ok(core) => {
+                if core.migrated() {
+                    migrated.push((root.join(CORE_FILE), core.version));
+                }
+                references.set_core(core.references);
+                core.value
+            }
+            Err(e) => {
+                diagnostics.push(schema_error(CORE_FILE.into(), &content, &e));
+                skipped.push(root.join(CORE_FILE));
+                CoreConfiguration::default()
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => CoreConfiguration::default(),
+        Err(e) => {
+            diagnostics
+                .push(Diagnostic::error(format!("cannot read file: {e}")).in_file(CORE_FILE));
+            skipped.push(root.join(CORE_FILE));
+            CoreConfiguration::default()
+        }
+    };
+
+    let (applications, files) = read_applications(
+        root,
+        &mut diagnostics,
+        &mut skipped,
+        &mut migrated,
+        &mut references,
+    );
+
+    let configuration = CurrentConfiguration { core, applications };
+    diagnostics.extend(check(&configuration, &files));
+
+    Loaded {
+        configuration,
+        diagnostics,
+        skipped,
+        migrated,
+        references,
+    }
+}
+
+/// Validate a configuration, assuming each application is saved in the file named after it.
+pub fn validate(configuration: &CurrentConfiguration) -> Vec<Diagnostic> {
+    let files: Vec<String> = configuration
+        .applications
+        .iter()
+        .map(application_file)
+        .collect();
+
+    check(configuration, &files)
+}
+
+/// The file an application is saved in, relative to the configuration directory.
+pub fn application_file(application: &Application) -> String {
+    format!("{APPLICATIONS_DIRECTORY}/{}.toml", application.hostname)
+}
+
+/// Check the syntax of an application hostname, which may start with a `*.` wildcard.
+pub fn check_hostname(hostname: &str) -> Result<(), String> {
+    if hostname.is_empty() {
+        return Err("hostname is empty".into());
+    }
+
+    if hostname.contains(':') {
+        return Err("hostname should not include a port".into());
+    }
+
+    let name = hostname.strip_prefix(WILDCARD_PREFIX).unwrap_or(hostname);
+
+    if name.contains('*') {
+        return Err("a wildcard is only allowed as the first label, like `*.example.com`".into());
+    }
+
+    if name.len() > MAX_HOSTNAME_LENGTH {
+        return Err(format!(
+            "hostname is longer than {MAX_HOSTNAME_LENGTH} characters"
+        ));
+    }
+
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err("hostname has an empty label".into());
+        }
+
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(format!(
+                "label `{label}` is longer than {MAX_LABEL_LENGTH} characters"
+            ));
+        }
+
+        if let Some(c) = label
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+        {
+            return Err(format!("invalid character `{c}` in hostname"));
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label `{label}` starts or ends with `-`"));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_applications(
+    root: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+    skipped: &mut Vec<PathBuf>,
+    migrated: &mut Vec<(PathBuf, u32)>,
+    references: &mut References,
+) -> (Vec<Application>, Vec<String>) {
+    let mut applications = Vec::new();
+    let mut files = Vec::new();
+    let mut paths = Vec::new();
+    let mut read_references = Vec::new();
+
+    let entries = match fs::read_dir(root.join(APPLICATIONS_DIRECTORY)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return (applications, files),
+        Err(e) => {
+            diagnostics.push(
+                Diagnostic::error(format!("cannot read directory: {e}"))
+                    .in_file(APPLICATIONS_DIRECTORY),
+            );
+            return (applications, files);
+        }
+    };
+
+    // Report files in a stable order.
+    let mut entries: Vec<_> = entries.collect();
+    entries.sort_by_key(|entry| entry.as_ref().ok().map(|entry| entry.file_name()));
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                diagnostics.push(
+                    Diagnostic::error(format!("cannot read directory: {e}"))
+                        .in_file(APPLICATIONS_DIRECTORY),
+                );
+                continue;
+            }
+        };
+
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let file = format!("{APPLICATIONS_DIRECTORY}/{file_name}");
+
+        // Hidden files are temporary files or swap files of editors.
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        if !file_name.ends_with(".toml") {
+            diagnostics.push(
+                Diagnostic::error("unexpected file, application files end in `.toml`")
+                    .in_file(file),
+            );
+            skipped.push(entry.path());
+            continue;
+        }
+
+        let content = match fs::read_to_string(entry.path()) {
+            Ok(content) => content,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(format!("cannot read file: {e}")).in_file(file));
+                skipped.push(entry.path());
+                continue;
+            }
+        };
+
+        let application: Application =
+            match schema::read::<Application>(&content, Kind::Application) {
+                Ok(application) => {
+                    if application.migrated() {
+                        migrated.push((entry.path(), application.version));
+                    }
+                    read_references.push(application.references);
+                    application.value
+                }
+                Err(e) => {
+                    diagnostics.push(schema_error(file, &content, &e));
+                    skipped.push(entry.path());
+                    continue;
+                }
+            };
+
+        applications.push(application);
+        files.push(file);
+        paths.push(entry.path());
+    }
+
+    // Only one application is kept per hostname, preferably the one in the file named after it.
+    // Saving would delete the files of the others, so they are skipped instead.
+    let mut kept: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+
+    for (i, application) in applications.iter().enumerate() {
+        let named = files[i] == application_file(application);
+
+        match kept.entry(application.hostname.to_ascii_lowercase()) {
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+            }
+            Entry::Occupied(mut entry) => {
+                let first = *entry.get();
+                match named && files[first] != application_file(&applications[first]) {
+                    true => duplicates.push((entry.insert(i), i)),
+                    false => duplicates.push((i, first)),
+                }
+            }
+        }
+    }
+
+    for (duplicate, other) in duplicates.iter() {
+        diagnostics.push(
+            Diagnostic::error(format!(
+                "hostname `{}` is also used by `{}`",
+                applications[*duplicate].hostname, files[*other]
+            ))
+            .in_file(&files[*duplicate])
+            .at_field("hostname"),
+        );
+        skipped.push(paths[*duplicate].clone());
+    }
+
+    let (applications, files): (Vec<_>, Vec<_>) = applications
+        .into_iter()
+        .zip(files)
+        .zip(read_references)
+        .enumerate()
+        .filter(|(i, _)| !duplicates.iter().any(|(duplicate, _)| duplicate == i))
+        .map(|(_, (read, application_references))| {
+            references.set_application(&read.0.hostname, application_references);
+            read
+        })
+        .unzip();
+
+    for (application, file) in applications.iter().zip(files.iter()) {
+        if *file != application_file(application) {
+            diagnostics.push(
+                Diagnostic::warning(format!(
+                    "file name does not match the hostname, the application will be saved to `{}`",
+                    application_file(application)
+                ))
+                .in_file(file)
+                .at_field("hostname"),
+            );
+        }
+    }
+
+    (applications, files)
+}
+
+/// Report an error reading a configuration file.
+pub fn schema_error(file: String, content: &str, error: &SchemaError) -> Diagnostic {
+    match error {
+        SchemaError::Parse(e) => parse_error(file, content, e),
+        SchemaError::InvalidVersion(_) | SchemaError::Newer(_) => {
+            Diagnostic::error(error.to_string())
+                .in_file(file)
+                .at_field(VERSION_KEY)
+        }
+        SchemaError::Migration { .. } => Diagnostic::error(error.to_string()).in_file(file),
+        SchemaError::Interpolation(e) => Diagnostic::error(e.reason.clone())
+            .in_file(file)
+            .at_field(e.field.clone()),
+    }
+}
+
+fn parse_error(file: String, content: &str, error: &toml::de::Error) -> Diagnostic {
+    let message = match error.span() {
+        Some(span) => {
+            let line = content.as_bytes()[..span.start.min(content.len())]
+                .iter()
+                .filter(|b| **b == b'\n')
+                .count()
+                + 1;
+            format!("{} (line {line})", error.message())
+        }
+        None => error.message().to_owned(),
+    };
+
+    Diagnostic::error(message).in_file(file)
+}
+
+/// Validate `configuration`, where application `i` was read from `files[i]`.
+fn check(configuration: &CurrentConfiguration, files: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let listeners = configuration.core.listeners();
+
+    check_listeners(&configuration.core, &listeners, &mut diagnostics);
+
+    if configuration.core.access.as_ref().is_some_and(|access| {
+        access.admin.is_empty() && access.operator.is_empty() && access.read_only.is_empty()
+    }) {
+        diagnostics.push(
+            Diagnostic::warning("nobody has a role, only root can use `sail`")
+                .in_file(CORE_FILE)
+                .at_field("access"),
+        );
+    }
+
+    let mut hostnames: HashMap<String, &str> = HashMap::new();
+
+    for (application, file) in configuration.applications.iter().zip(files) {
+        if let Err(message) = check_hostname(&application.hostname) {
+            diagnostics.push(
+                Diagnostic::error(message)
+                    .in_file(file)
+                    .at_field("hostname"),
+            );
+        }
+
+        if let Some(other) = hostnames.insert(application.hostname.to_ascii_lowercase(), file) {
+            diagnostics.push(
+                Diagnostic::error(format!(
+                    "hostname `{}` is also used by `{other}`",
+                    application.hostname
+                ))
+                .in_file(file)
+                .at_field("hostname"),
+            );
+        }
+
+        if let Err(message) = check_upstream(application, &listeners, &configuration.core) {
+            diagnostics.push(Diagnostic::error(message).in_file(file).at_field("address"));
+        }
+    }
+
+    diagnostics
+}
+
+fn check_listeners(
+    core: &CoreConfiguration,
+    listeners: &[ListenerConfiguration],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // Without any `listeners`, the default listener is configured through `port`.
+    let field = |i: usize, name: &str| match core.listeners.is_empty() {
+        true => name.to_owned(),
+        false => format!("listeners[{i}].{name}"),
+    };
+
+    for (i, listener) in listeners.iter().enumerate() {
+        if listener.port == 0 {
+            diagnostics.push(
+                Diagnostic::error("port 0 is not allowed")
+                    .in_file(CORE_FILE)
+                    .at_field(field(i, "port")),
+            );
+        }
+
+        for (j, other) in listeners[..i].iter().enumerate() {
+            if overlaps(listener, other) {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "{} conflicts with listeners[{j}] on {}",
+                        listener.socket_address(),
+                        other.socket_address()
+                    ))
+                    .in_file(CORE_FILE)
+                    .at_field(field(i, "port")),
+                );
+            }
+
+            if listener.name.is_some() && listener.name == other.name {
+                diagnostics.push(
+                    Diagnostic::error("another listener has the same name")
+                        .in_file(CORE_FILE)
+                        .at_field(field(i, "name")),
+                );
+            }
+        }
+
+        if let Some(metrics_port) = core.metrics_port {
+            if listener.port == metrics_port && serves_loopback(listener) {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "port {metrics_port} is also used for metrics on 127.0.0.1"
+                    ))
+                    .in_file(CORE_FILE)
+                    .at_field(field(i, "port")),
+                );
+            }
+        }
+
+        if let Some(tls) = &listener.tls {
+            for (name, path) in [("certificate", &tls.certificate), ("key", &tls.key)] {
+                if let Err(e) = fs::File::open(path) {
+                    diagnostics.push(
+                        Diagnostic::error(format!("cannot read `{}`: {e}", path.display()))
+                            .in_file(CORE_FILE)
+                            .at_field(field(i, &format!("tls.{name}"))),
+                    );
+                }
+            }
+        }
+
+        if let Some(proxy_protocol) = &listener.proxy_protocol {
+            if proxy_protocol.trusted.is_empty() {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        "no trusted ranges, PROXY protocol headers are never accepted",
+                    )
+                    .in_file(CORE_FILE)
+                    .at_field(field(i, "proxy_protocol.trusted")),
+                );
+            }
+        }
+    }
+}
+
+fn check_upstream(
+    application: &Application,
+    listeners: &[ListenerConfiguration],
+    core: &CoreConfiguration,
+) -> Result<(), String> {
+    let address = application.address;
+    let ip = address.ip();
+
+    if address.port() == 0 {
+        return Err("port 0 is not allowed".into());
+    }
+
+    if ip.is_unspecified() {
+        return Err(format!("cannot forward to the unspecified address {ip}"));
+    }
+
+    if ip.is_multicast() || ip == IpAddr::from([255, 255, 255, 255]) {
+        return Err(format!(
+            "cannot forward to the broadcast or multicast address {ip}"
+        ));
+    }
+
+    for listener in listeners {
+        let own = listener.port == address.port()
+            && (listener.address == ip || (listener.address.is_unspecified() && ip.is_loopback()));
+
+        if own {
+            return Err(format!(
+                "{address} is served by Sail itself, forwarding to it would loop"
+            ));
+        }
+    }
+
+    if core.metrics_port == Some(address.port()) && ip == IpAddr::from([127, 0, 0, 1]) {
+        return Err(format!("{address} serves Sail's metrics"));
+    }
+
+    Ok(())
+}
+
+/// Whether two listeners would try to bind to the same address.
+fn overlaps(a: &ListenerConfiguration, b: &ListenerConfiguration) -> bool {
+    if a.port != b.port {
+        return false;
+    }
+
+    a.address == b.address || covers(a, b.address) || covers(b, a.address)
+}
+
+/// Whether a listener on an unspecified address also accepts connections to `address`.
+fn covers(listener: &ListenerConfiguration, address: IpAddr) -> bool {
+    if !listener.address.is_unspecified() {
+        return false;
+    }
+
+    match (listener.address, address) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => true,
+        // A dual-stack listener accepts IPv4 connections as well.
+        (IpAddr::V6(_), IpAddr::V4(_)) => !listener.ipv6_only,
+        (IpAddr::V4(_), IpAddr::V6(_)) => false,
+    }
+}
+
+/// Whether a listener accepts connections to `127.0.0.1`, where the metrics are served.
+fn serves_loopback(listener: &ListenerConfiguration) -> bool {
+    listener.address == IpAddr::from([127, 0, 0, 1]) || covers(listener, [127, 0, 0, 1].into())
+}
@@ -0,0 +1,54 @@
+use crate::control::Request;
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+/// What a caller on the control socket is allowed to do. Every role can do what the roles
+/// before it can.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Inspect the configuration, without secrets.
+    ReadOnly,
+    /// Manage applications and their secrets and upload keys.
+    Operator,
+    /// Change the core configuration, read secrets and the audit log.
+    Admin,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::ReadOnly => write!(f, "read_only"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl Request {
+    /// The role a caller needs to make this request.
+    pub fn required_role(&self) -> Role {
+        match self {
+            Request::GetApplications
+            | Request::Status
+            | Request::ValidateConfiguration
+            | Request::GetHistory
+            | Request::DiffConfiguration { .. }
+            | Request::PlanApplications { .. }
+            | Request::ListSecrets { .. }
+            | Request::ExportConfiguration { secrets: false, .. } => Role::ReadOnly,
+            Request::CreateApplication { .. }
+            | Request::DeleteApplication { .. }
+            | Request::Rollback { .. }
+            | Request::ApplyPlan { .. }
+            | Request::SetSecret { .. }
+            | Request::DeleteSecret { .. }
+            | Request::RotateUploadKey { .. }
+            | Request::VerifyUploadKey { .. } => Role::Operator,
+            Request::ImportConfiguration { .. }
+            | Request::ExportConfiguration { secrets: true, .. }
+            | Request::GetSecret { .. }
+            | Request::GetAuditLog { .. } => Role::Admin,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::control::{Request, Response};
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+/// The process on the other end of a control connection, from `SO_PEERCRED`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+    /// Name of the user with `uid`, if it has one.
+    pub user: Option<String>,
+}
+
+impl Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{user} (uid {})", self.uid),
+            None => write!(f, "uid {}", self.uid),
+        }
+    }
+}
+
+/// A control request as recorded in the audit log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Who sent the request, unless their credentials could not be read.
+    pub caller: Option<Caller>,
+    /// The request, with secret values left out.
+    pub request: Request,
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure { message: String },
+}
+
+impl From<&Response> for Outcome {
+    fn from(response: &Response) -> Self {
+        match response {
+            Response::Error { message } => Outcome::Failure {
+                message: message.clone(),
+            },
+            _ => Outcome::Success,
+        }
+    }
+}
+
+/// Which entries of the audit log to return. Every condition that is set must match.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AuditFilter {
+    /// Name or uid of the caller.
+    pub user: Option<String>,
+    /// Name of the request, like `create_application`.
+    pub request: Option<String>,
+    /// Only entries at or after this time, in seconds since the Unix epoch.
+    pub since: Option<u64>,
+    /// Only requests that failed.
+    pub failed: bool,
+    /// Only the most recent entries, at most this many.
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let user = match (&self.user, &entry.caller) {
+            (None, _) => true,
+            (Some(user), Some(caller)) => {
+                caller.user.as_ref() == Some(user) || caller.uid.to_string() == *user
+            }
+            (Some(_), None) => false,
+        };
+
+        user && self
+            .request
+            .as_ref()
+            .is_none_or(|request| entry.request.name() == request)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && (!self.failed || matches!(entry.outcome, Outcome::Failure { .. }))
+    }
+}
@@ -1,5 +1,13 @@
-use super::application::Application;
+use super::{
+    application::Application,
+    audit::{AuditEntry, AuditFilter},
+    diagnostic::Diagnostic,
+    export::{Format, ImportMode},
+    history::HistoryEntry,
+    plan::Plan,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Message {
@@ -15,11 +23,72 @@ pub struct Reply {
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum Request {
-    CreateApplication { application: Application },
-    DeleteApplication { hostname: String },
+    CreateApplication {
+        application: Application,
+    },
+    DeleteApplication {
+        hostname: String,
+    },
     GetApplications,
     Status,
     ValidateConfiguration,
+    GetHistory,
+    DiffConfiguration {
+        from: u64,
+        to: u64,
+    },
+    Rollback {
+        number: u64,
+    },
+    PlanApplications {
+        applications: Vec<Application>,
+        prune: bool,
+    },
+    /// Apply a plan, which is rejected if the configuration changed since it was computed.
+    ApplyPlan {
+        plan: Plan,
+    },
+    /// Export the complete configuration as a single document. With `secrets`, the TLS
+    /// certificates and keys of the listeners and the secrets of the applications are embedded.
+    ExportConfiguration {
+        format: Format,
+        secrets: bool,
+    },
+    ImportConfiguration {
+        document: String,
+        format: Format,
+        mode: ImportMode,
+    },
+    SetSecret {
+        hostname: String,
+        name: String,
+        value: String,
+    },
+    GetSecret {
+        hostname: String,
+        name: String,
+    },
+    ListSecrets {
+        hostname: String,
+    },
+    DeleteSecret {
+        hostname: String,
+        name: String,
+    },
+    /// Replace the upload key of an application. The current key keeps working for
+    /// `grace_period` seconds.
+    RotateUploadKey {
+        hostname: String,
+        grace_period: u64,
+    },
+    /// Check an upload key of an application, like an upload does.
+    VerifyUploadKey {
+        hostname: String,
+        key: String,
+    },
+    GetAuditLog {
+        filter: AuditFilter,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -31,8 +100,141 @@ pub enum Response {
     Status {
         port: u16,
         applications: Vec<Application>,
+        /// Problems found in the configuration on disk, the daemon runs in a degraded mode
+        /// when there are errors among them.
+        #[serde(default)]
+        problems: Vec<Diagnostic>,
     },
     Applications {
         applications: Vec<Application>,
     },
+    Diagnostics {
+        diagnostics: Vec<Diagnostic>,
+    },
+    History {
+        entries: Vec<HistoryEntry>,
+    },
+    Changes {
+        changes: Vec<String>,
+    },
+    Document {
+        document: String,
+    },
+    Secret {
+        value: String,
+    },
+    Secrets {
+        names: Vec<String>,
+    },
+    /// A new upload key, which is only returned this once.
+    UploadKey {
+        key: String,
+    },
+    /// A change was made. The upload keys of the applications it created, by hostname, are only
+    /// returned this once.
+    Applied {
+        changes: Vec<String>,
+        keys: BTreeMap<String, String>,
+    },
+    Plan {
+        plan: Plan,
+        /// Errors in the configuration the plan would result in, it cannot be applied if
+        /// there are any.
+        diagnostics: Vec<Diagnostic>,
+    },
+    AuditLog {
+        entries: Vec<AuditEntry>,
+    },
 }
+
+/// Implement [`Request::name`] and [`Request::NAMES`] from one list, which the match keeps
+/// complete.
+macro_rules! names {
+    ($($variant:ident => $name:literal,)*) => {
+        impl Request {
+            /// The name of every request, which are advertised as capabilities.
+            pub const NAMES: &'static [&'static str] = &[$($name),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Request::$variant { .. } => $name,)*
+                }
+            }
+        }
+    };
+}
+
+names! {
+    CreateApplication => "create_application",
+    DeleteApplication => "delete_application",
+    GetApplications => "get_applications",
+    Status => "status",
+    ValidateConfiguration => "validate_configuration",
+    GetHistory => "get_history",
+    DiffConfiguration => "diff_configuration",
+    Rollback => "rollback",
+    PlanApplications => "plan_applications",
+    ApplyPlan => "apply_plan",
+    ExportConfiguration => "export_configuration",
+    ImportConfiguration => "import_configuration",
+    SetSecret => "set_secret",
+    GetSecret => "get_secret",
+    ListSecrets => "list_secrets",
+    DeleteSecret => "delete_secret",
+    RotateUploadKey => "rotate_upload_key",
+    VerifyUploadKey => "verify_upload_key",
+    GetAuditLog => "get_audit_log",
+}
+
+impl Request {
+    /// Whether the request is recorded in the audit log: it changes something, or reveals a
+    /// secret.
+    pub fn is_audited(&self) -> bool {
+        match self {
+            Request::CreateApplication { .. }
+            | Request::DeleteApplication { .. }
+            | Request::Rollback { .. }
+            | Request::ApplyPlan { .. }
+            | Request::ImportConfiguration { .. }
+            | Request::SetSecret { .. }
+            | Request::GetSecret { .. }
+            | Request::DeleteSecret { .. }
+            | Request::RotateUploadKey { .. } => true,
+            Request::ExportConfiguration { secrets, .. } => *secrets,
+            Request::GetApplications
+            | Request::Status
+            | Request::ValidateConfiguration
+            | Request::GetHistory
+            | Request::DiffConfiguration { .. }
+            | Request::PlanApplications { .. }
+            | Request::ListSecrets { .. }
+            | Request::VerifyUploadKey { .. }
+            | Request::GetAuditLog { .. } => false,
+        }
+    }
+
+    /// The request without secret values, to record it.
+    pub fn redacted(&self) -> Request {
+        match self {
+            Request::SetSecret { hostname, name, .. } => Request::SetSecret {
+                hostname: hostname.clone(),
+                name: name.clone(),
+                value: REDACTED.into(),
+            },
+            Request::VerifyUploadKey { hostname, .. } => Request::VerifyUploadKey {
+                hostname: hostname.clone(),
+                key: REDACTED.into(),
+            },
+            // Documents can embed TLS keys and secrets.
+            Request::ImportConfiguration { format, mode, .. } => Request::ImportConfiguration {
+                document: REDACTED.into(),
+                format: *format,
+                mode: *mode,
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+/// Written in place of secret values.
+pub const REDACTED: &str = "<redacted>";
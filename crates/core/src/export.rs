@@ -0,0 +1,44 @@
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+/// Format of an exported configuration.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Toml,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            other => Err(other.to_string()),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Toml => write!(f, "toml"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// How an imported configuration is combined with the current one.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add the imported applications and update those with the same hostname, keeping the other
+    /// applications and the core configuration.
+    #[default]
+    Merge,
+    /// Use the imported core configuration and applications only.
+    Replace,
+}
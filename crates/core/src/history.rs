@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// A change to the configuration, as recorded in the history.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    /// Number of the snapshot of the configuration after this change.
+    pub number: u64,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Who made the change, like `jens (uid 1000)`.
+    pub author: String,
+    pub description: String,
+    /// What changed compared to the previous snapshot.
+    pub changes: Vec<String>,
+}
@@ -1,3 +1,11 @@
+pub mod access;
 pub mod application;
+pub mod audit;
 pub mod control;
+pub mod diagnostic;
+pub mod export;
+pub mod history;
+pub mod plan;
+pub mod protocol;
 pub mod proxy;
+pub mod upload_key;
@@ -0,0 +1,212 @@
+use super::application::Application;
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+
+/// The changes needed to go from the current applications to a desired set of applications.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Plan {
+    /// Number of the configuration in the history that the plan was computed against.
+    pub base: u64,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Action {
+    Create { application: Application },
+    Update { from: Application, to: Application },
+    Delete { application: Application },
+}
+
+impl Plan {
+    /// Compare `current` with `desired`. Applications missing from `desired` are only deleted
+    /// when `prune` is set.
+    pub fn compute(
+        base: u64,
+        current: &[Application],
+        desired: &[Application],
+        prune: bool,
+    ) -> Self {
+        let mut actions = Vec::new();
+
+        for application in desired {
+            match current.iter().find(|a| a.hostname == application.hostname) {
+                None => actions.push(Action::Create {
+                    application: application.clone(),
+                }),
+                Some(existing) if existing != application => actions.push(Action::Update {
+                    from: existing.clone(),
+                    to: application.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+
+        if prune {
+            for application in current {
+                if !desired.iter().any(|a| a.hostname == application.hostname) {
+                    actions.push(Action::Delete {
+                        application: application.clone(),
+                    });
+                }
+            }
+        }
+
+        Self { base, actions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// The applications after applying the plan to `current`.
+    pub fn apply(&self, current: &[Application]) -> Vec<Application> {
+        let mut applications = current.to_vec();
+
+        for action in self.actions.iter() {
+            match action {
+                Action::Create { application } => applications.push(application.clone()),
+                Action::Update { to, .. } => {
+                    if let Some(existing) =
+                        applications.iter_mut().find(|a| a.hostname == to.hostname)
+                    {
+                        *existing = to.clone();
+                    }
+                }
+                Action::Delete { application } => {
+                    applications.retain(|a| a.hostname != application.hostname)
+                }
+            }
+        }
+
+        applications
+    }
+
+    /// Count the actions of each kind: (create, update, delete).
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.actions
+            .iter()
+            .fold((0, 0, 0), |(create, update, delete), action| match action {
+                Action::Create { .. } => (create + 1, update, delete),
+                Action::Update { .. } => (create, update + 1, delete),
+                Action::Delete { .. } => (create, update, delete + 1),
+            })
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Create { application } => write!(
+                f,
+                "+ create {} -> {}",
+                application.hostname, application.address
+            ),
+            Action::Update { from, to } => write!(
+                f,
+                "~ update {}: {} -> {}",
+                to.hostname, from.address, to.address
+            ),
+            Action::Delete { application } => write!(
+                f,
+                "- delete {} -> {}",
+                application.hostname, application.address
+            ),
+        }
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (create, update, delete) = self.counts();
+
+        write!(
+            f,
+            "{create} to create, {update} to update, {delete} to delete"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn application(hostname: &str, port: u16) -> Application {
+        Application {
+            hostname: hostname.into(),
+            address: ([127, 0, 0, 1], port).into(),
+        }
+    }
+
+    #[test]
+    fn compute() {
+        let current = [
+            application("same.example.com", 9001),
+            application("changed.example.com", 9002),
+            application("missing.example.com", 9003),
+        ];
+        let desired = [
+            application("same.example.com", 9001),
+            application("changed.example.com", 9012),
+            application("new.example.com", 9004),
+        ];
+
+        let plan = Plan::compute(7, &current, &desired, false);
+
+        assert_eq!(plan.base, 7);
+        assert_eq!(
+            plan.actions,
+            [
+                Action::Update {
+                    from: application("changed.example.com", 9002),
+                    to: application("changed.example.com", 9012),
+                },
+                Action::Create {
+                    application: application("new.example.com", 9004),
+                },
+            ]
+        );
+        assert_eq!(plan.counts(), (1, 1, 0));
+
+        let pruned = Plan::compute(7, &current, &desired, true);
+        assert_eq!(pruned.counts(), (1, 1, 1));
+        assert_eq!(
+            pruned.actions.last(),
+            Some(&Action::Delete {
+                application: application("missing.example.com", 9003),
+            })
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let current = [
+            application("same.example.com", 9001),
+            application("changed.example.com", 9002),
+            application("missing.example.com", 9003),
+        ];
+        let desired = [
+            application("changed.example.com", 9012),
+            application("new.example.com", 9004),
+            application("same.example.com", 9001),
+        ];
+
+        let mut applied = Plan::compute(0, &current, &desired, true).apply(&current);
+        applied.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        assert_eq!(applied, desired);
+
+        let kept = Plan::compute(0, &current, &desired, false).apply(&current);
+        assert!(kept.contains(&application("missing.example.com", 9003)));
+        assert_eq!(kept.len(), 4);
+    }
+
+    #[test]
+    fn unchanged() {
+        let current = [application("same.example.com", 9001)];
+
+        let plan = Plan::compute(0, &current, &current, true);
+
+        assert!(plan.is_empty());
+        assert_eq!(plan.apply(&current), current);
+    }
+}
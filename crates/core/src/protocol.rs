@@ -0,0 +1,158 @@
+use crate::control::Request;
+use core::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// Version of the control protocol: the handshake, and the framing and meaning of [`Message`]
+/// and [`Reply`]. Bump it for changes that older peers would misunderstand, and raise
+/// [`MIN_PROTOCOL_VERSION`] when support for older versions is dropped. Requests that are added
+/// are advertised as capabilities instead.
+///
+/// [`Message`]: crate::control::Message
+/// [`Reply`]: crate::control::Reply
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest version of the control protocol that is still supported.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The name of every request this version supports, see [`Request::name`].
+pub const REQUESTS: &[&str] = Request::NAMES;
+
+/// Sent by both sides when a control connection starts, `sail` first.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Hello {
+    /// Newest protocol version this side speaks.
+    pub protocol: u32,
+    /// Oldest protocol version this side speaks.
+    pub min_protocol: u32,
+    /// Name of the program, `sail` or `saild`, for error messages.
+    pub program: String,
+    /// Version of the program, for error messages.
+    pub version: String,
+    /// The requests this side supports.
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    pub fn new(program: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            program: program.into(),
+            version: version.into(),
+            capabilities: REQUESTS.iter().map(|request| request.to_string()).collect(),
+        }
+    }
+
+    /// The protocol version to speak with `other`, the newest both support.
+    pub fn negotiate(&self, other: &Hello) -> Result<u32, Incompatible> {
+        let version = self.protocol.min(other.protocol);
+
+        if version < self.min_protocol || version < other.min_protocol {
+            return Err(Incompatible {
+                ours: Box::new(self.clone()),
+                theirs: Box::new(other.clone()),
+            });
+        }
+
+        Ok(version)
+    }
+
+    pub fn supports(&self, request: &Request) -> bool {
+        self.capabilities
+            .iter()
+            .any(|capability| capability == request.name())
+    }
+}
+
+/// The two sides of a connection have no protocol version in common.
+#[derive(Debug)]
+pub struct Incompatible {
+    pub ours: Box<Hello>,
+    pub theirs: Box<Hello>,
+}
+
+impl Incompatible {
+    /// The side that should be upgraded.
+    fn older(&self) -> &Hello {
+        match self.ours.protocol < self.theirs.protocol {
+            true => &self.ours,
+            false => &self.theirs,
+        }
+    }
+}
+
+impl Display for Incompatible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = |hello: &Hello| match hello.min_protocol == hello.protocol {
+            true => format!("version {}", hello.protocol),
+            false => format!("versions {} to {}", hello.min_protocol, hello.protocol),
+        };
+
+        write!(
+            f,
+            "{} {} speaks control protocol {} and {} {} speaks {}, upgrade {}",
+            self.ours.program,
+            self.ours.version,
+            range(&self.ours),
+            self.theirs.program,
+            self.theirs.version,
+            range(&self.theirs),
+            self.older().program,
+        )
+    }
+}
+
+impl Error for Incompatible {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(program: &str, min_protocol: u32, protocol: u32) -> Hello {
+        Hello {
+            protocol,
+            min_protocol,
+            ..Hello::new(program, "0.1.0")
+        }
+    }
+
+    #[test]
+    fn negotiate_newest_common_version() {
+        let sail = hello("sail", 1, 3);
+        let saild = hello("saild", 2, 4);
+
+        assert_eq!(sail.negotiate(&saild).unwrap(), 3);
+        assert_eq!(saild.negotiate(&sail).unwrap(), 3);
+        assert_eq!(sail.negotiate(&sail).unwrap(), 3);
+    }
+
+    #[test]
+    fn negotiate_incompatible() {
+        let sail = hello("sail", 1, 2);
+        let saild = hello("saild", 3, 4);
+
+        let error = sail.negotiate(&saild).unwrap_err();
+        assert_eq!(error.older().program, "sail");
+        assert_eq!(
+            error.to_string(),
+            "sail 0.1.0 speaks control protocol versions 1 to 2 and saild 0.1.0 speaks versions 3 to 4, upgrade sail"
+        );
+
+        let error = saild.negotiate(&sail).unwrap_err();
+        assert_eq!(error.older().program, "sail");
+    }
+
+    #[test]
+    fn capabilities() {
+        let sail = Hello::new("sail", "0.1.0");
+        assert!(sail.supports(&Request::Status));
+
+        let old = Hello {
+            capabilities: vec!["status".into()],
+            ..sail
+        };
+        assert!(old.supports(&Request::Status));
+        assert!(!old.supports(&Request::GetApplications));
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+/// Header carrying the unique ID of a request, both towards applications and back to clients.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum ProxyError {
     FetchError(FetchError),
@@ -11,3 +14,13 @@ pub enum FetchError {
     Handshake(String),
     Send(String),
 }
+
+impl FetchError {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FetchError::Connection(_) => "connection",
+            FetchError::Handshake(_) => "handshake",
+            FetchError::Send(_) => "send",
+        }
+    }
+}
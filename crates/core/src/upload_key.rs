@@ -0,0 +1,92 @@
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Length of an upload key in bytes, before it is hex encoded.
+const KEY_LENGTH: usize = 32;
+
+/// Generate a new upload key.
+pub fn generate() -> String {
+    let mut key = [0; KEY_LENGTH];
+    OsRng.fill_bytes(&mut key);
+
+    hex(&key)
+}
+
+/// Hash an upload key, the hash is stored instead of the key itself.
+pub fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The hashes of the upload keys of an application.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UploadKeys {
+    pub hash: String,
+    /// The key that was replaced by the current one, which keeps working for a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PreviousKey>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PreviousKey {
+    pub hash: String,
+    /// Seconds since the Unix epoch after which the key no longer works.
+    pub expires: u64,
+}
+
+impl UploadKeys {
+    pub fn new(key: &str) -> Self {
+        Self {
+            hash: hash(key),
+            previous: None,
+        }
+    }
+
+    /// Replace the current key with `key`. With `expires`, the current key keeps working until
+    /// then.
+    pub fn rotate(&mut self, key: &str, expires: Option<u64>) {
+        let previous = std::mem::replace(&mut self.hash, hash(key));
+
+        self.previous = expires.map(|expires| PreviousKey {
+            hash: previous,
+            expires,
+        });
+    }
+
+    /// Whether `key` is the current key, or the previous key before it expires at `now`.
+    pub fn verify(&self, key: &str, now: u64) -> bool {
+        let hash = hash(key);
+
+        let current = equal(&hash, &self.hash);
+        let previous = self
+            .previous
+            .as_ref()
+            .is_some_and(|previous| equal(&hash, &previous.hash) && now < previous.expires);
+
+        current || previous
+    }
+
+    /// Forget the previous key once it expired at `now`.
+    pub fn expire(&mut self, now: u64) {
+        if self
+            .previous
+            .as_ref()
+            .is_some_and(|previous| now >= previous.expires)
+        {
+            self.previous = None;
+        }
+    }
+}
+
+/// Compare in constant time, so the time taken doesn't reveal how much of a hash matched.
+fn equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
@@ -0,0 +1,114 @@
+use sail_core::{
+    audit::{AuditEntry, AuditFilter, Caller, Outcome},
+    control::Request,
+};
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
+use tracing::{error, warn};
+
+/// File inside the configuration directory with the audit log.
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+/// Append-only record of the control requests that change something or reveal a secret, one
+/// JSON object per line.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Held while appending, so entries are never interleaved.
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        let path = root.join(AUDIT_LOG_FILE);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(&path)
+            .await?;
+
+        // A crash can leave the last entry incomplete, end it so the next entry is not appended
+        // to it.
+        if !ends_with_newline(&mut file).await? {
+            warn!("`{}` ends in an incomplete entry", path.display());
+            file.write_all(b"\n").await?;
+            file.sync_data().await?;
+        }
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append an entry and sync it to disk. Failures are logged, the request was already handled.
+    pub async fn record(&self, caller: Option<Caller>, request: &Request, outcome: Outcome) {
+        let entry = AuditEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            caller,
+            request: request.redacted(),
+            outcome,
+        };
+
+        let mut line = serde_json::to_string(&entry).expect("audit entry should be serializable");
+        line.push('\n');
+
+        let mut file = self.file.lock().await;
+
+        if let Err(e) = async {
+            file.write_all(line.as_bytes()).await?;
+            file.sync_data().await
+        }
+        .await
+        {
+            error!("failed to write to the audit log: {e}")
+        }
+    }
+
+    /// The entries that match `filter`, oldest first.
+    pub async fn entries(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+        let content = fs::read_to_string(&self.path).await?;
+        let mut entries = Vec::new();
+
+        for (index, line) in content.lines().enumerate() {
+            match serde_json::from_str::<AuditEntry>(line) {
+                Ok(entry) if filter.matches(&entry) => entries.push(entry),
+                Ok(_) => {}
+                // A crash can leave the last line incomplete.
+                Err(e) => warn!(
+                    "skipping line {} of `{}`: {e}",
+                    index + 1,
+                    self.path.display()
+                ),
+            }
+        }
+
+        if let Some(limit) = filter.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+
+        Ok(entries)
+    }
+}
+
+async fn ends_with_newline(file: &mut File) -> io::Result<bool> {
+    if file.metadata().await?.len() == 0 {
+        return Ok(true);
+    }
+
+    file.seek(SeekFrom::End(-1)).await?;
+    Ok(file.read_u8().await? == b'\n')
+}
@@ -1,154 +1,388 @@
-use sail_config::{Configurable, CoreConfiguration, CurrentConfiguration};
-use sail_core::application::Application;
-use std::sync::{Arc, Mutex};
-use tokio::fs;
-use tracing::{error, info};
+mod backend;
+mod history;
+mod persistence;
+mod reload;
+mod secrets;
+mod transfer;
+mod upload_keys;
 
-const DEFAULT_PORT: u16 = 4250;
+pub use backend::{Backend, BackendKind};
+pub use reload::watch;
+pub use secrets::SecretStore;
+pub use upload_keys::UploadKeyStore;
 
+use arc_swap::ArcSwap;
+use sail_config::{
+    events::{Update, UPDATES_CAPACITY},
+    interpolation::References,
+    schema::SCHEMA_VERSION,
+    Change, Configurable, CurrentConfiguration, RoutingTable,
+};
+use sail_core::diagnostic::Diagnostic;
+use std::{io, path::PathBuf, sync::Arc};
+use tokio::{
+    fs,
+    sync::{broadcast, Mutex, MutexGuard},
+};
+use tracing::{error, info, warn};
+
+/// Directory the configuration is kept in, unless another one is given with `--config-dir` or
+/// `SAIL_CONFIG_DIR`.
+pub const DEFAULT_ROOT: &str = "/etc/sail";
+
+/// Author of changes made by the daemon itself, like loading the configuration.
+const AUTHOR: &str = "saild";
+
+/// The current configuration and the routing table compiled from it, published through an
+/// atomic pointer swap so request routing never waits on a lock.
 pub struct Configuration {
-    options: Mutex<Arc<CurrentConfiguration>>,
+    /// The configuration directory, which also holds the files that are not part of the
+    /// configuration, like the secrets.
+    root: PathBuf,
+    current: ArcSwap<Current>,
+    problems: ArcSwap<Vec<Diagnostic>>,
+    /// Values that were written as references in the files, which are saved as references.
+    references: ArcSwap<References>,
+    backend: Backend,
+    secrets: SecretStore,
+    upload_keys: UploadKeyStore,
+    updates: broadcast::Sender<Arc<Update>>,
+    /// Held from reading the configuration until a changed one is set, see
+    /// [`Configuration::lock`].
+    changing: Mutex<()>,
+}
+
+/// A configuration together with its routing table, swapped as one so readers never pair a
+/// configuration with the routes of another.
+struct Current {
+    options: Arc<CurrentConfiguration>,
+    routes: Arc<RoutingTable>,
+}
+
+impl Current {
+    fn new(options: Arc<CurrentConfiguration>) -> Self {
+        Self {
+            routes: Arc::new(RoutingTable::new(&options)),
+            options,
+        }
+    }
 }
 
 impl Configurable for Configuration {
     fn get(&self) -> Arc<CurrentConfiguration> {
-        Arc::clone(
-            &*self
-                .options
-                .lock()
-                .expect("should be able to get lock on configuration for retrieval"),
-        )
+        self.current.load().options.clone()
     }
 
-    async fn set(&self, new: CurrentConfiguration) {
-        *self
-            .options
-            .lock()
-            .expect("should be able to get lock on configuration for modification") = Arc::new(new);
+    fn routes(&self) -> Arc<RoutingTable> {
+        self.current.load().routes.clone()
+    }
+
+    fn problems(&self) -> Arc<Vec<Diagnostic>> {
+        self.problems.load_full()
+    }
 
-        self.save().await;
+    fn subscribe(&self) -> broadcast::Receiver<Arc<Update>> {
+        self.updates.subscribe()
+    }
+
+    async fn set(&self, new: CurrentConfiguration, change: Change) -> io::Result<()> {
+        let old = self.get();
+
+        self.backend
+            .commit(&old, &new, &self.references(), change)
+            .await?;
+        self.replace(new);
+
+        Ok(())
     }
 }
 
 impl Configuration {
-    pub async fn from_filesystem() -> Self {
-        if let Ok(m) = fs::metadata("/etc/sail").await {
-            if !m.is_dir() {
-                panic!("Unexpected file at `/etc/sail`, please remove")
-            }
+    fn new(
+        root: PathBuf,
+        options: CurrentConfiguration,
+        problems: Vec<Diagnostic>,
+        references: References,
+        backend: Backend,
+        secrets: SecretStore,
+        upload_keys: UploadKeyStore,
+    ) -> Self {
+        Self {
+            root,
+            current: ArcSwap::from_pointee(Current::new(Arc::new(options))),
+            problems: ArcSwap::from_pointee(problems),
+            references: ArcSwap::from_pointee(references),
+            backend,
+            secrets,
+            upload_keys,
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+            changing: Mutex::new(()),
+        }
+    }
+
+    /// Wait until no other change is being made. Changes hold the guard from reading the
+    /// configuration they change until they have set the result, so they never overwrite each
+    /// other or a reload.
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.changing.lock().await
+    }
+
+    /// Where the configuration and its history are stored.
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    pub fn secrets(&self) -> &SecretStore {
+        &self.secrets
+    }
+
+    pub fn upload_keys(&self) -> &UploadKeyStore {
+        &self.upload_keys
+    }
+
+    /// Delete the secrets and upload keys of an application that was deleted. They are kept when
+    /// an application is removed any other way, like a rollback, which is not the end of it.
+    pub async fn forget(&self, hostname: &str) {
+        if let Err(e) = self.secrets.remove_application(hostname).await {
+            error!("failed to delete the secrets of {hostname}: {e}")
         }
+        if let Err(e) = self.upload_keys.remove_application(hostname).await {
+            error!("failed to delete the upload keys of {hostname}: {e}")
+        }
+    }
 
-        let core_configuration: CoreConfiguration =
-            match fs::read_to_string("/etc/sail/configuration.toml")
+    pub fn references(&self) -> Arc<References> {
+        self.references.load_full()
+    }
+
+    /// Keep the references of a configuration that is about to be set, like one rolled back to,
+    /// so they are saved as references.
+    pub fn add_references(&self, references: References) {
+        let mut merged = (*self.references()).clone();
+        merged.merge(references);
+
+        self.references.store(Arc::new(merged));
+    }
+
+    /// Record the current configuration in the history, as changed from `old` by `change`.
+    async fn record(&self, old: &CurrentConfiguration, change: Change) {
+        let references = self.references();
+
+        if let Err(e) = self
+            .backend
+            .record(old, &self.get(), &references, change)
+            .await
+        {
+            error!("failed to record configuration change in history: {e}")
+        }
+    }
+
+    /// Swap the in-memory configuration without writing it to disk, and tell the subscribers
+    /// what changed.
+    fn replace(&self, new: CurrentConfiguration) {
+        let events = self.get().events(&new);
+        let new = Arc::new(new);
+
+        self.current.store(Arc::new(Current::new(new.clone())));
+
+        if !events.is_empty() {
+            // Nobody may be subscribed, which is fine.
+            let _ = self.updates.send(Arc::new(Update {
+                configuration: new,
+                events,
+            }));
+        }
+    }
+
+    /// Read the configuration from a backend in `root`. Entries that cannot be read or parsed are
+    /// moved aside and reported as problems, so the daemon still starts and serves every valid
+    /// application.
+    pub async fn open(root: PathBuf, kind: BackendKind) -> Self {
+        match fs::metadata(&root).await {
+            Ok(m) => {
+                if !m.is_dir() {
+                    panic!("Unexpected file at `{}`, please remove", root.display())
+                }
+            }
+            Err(_) => fs::create_dir_all(&root)
                 .await
-                .map(|s| toml::from_str(&s).expect("Configuration file should be valid TOML"))
-            {
-                Ok(config) => config,
-                Err(_) => CoreConfiguration { port: DEFAULT_PORT },
-            };
-
-        let applications = match fs::read_dir("/etc/sail/applications").await {
-            Ok(mut entries) => {
-                let mut apps = Vec::new();
-
-                while let Some(entry) = entries
-                    .next_entry()
-                    .await
-                    .expect("should be able to read application configuration directory entries")
-                {
-                    let file_name = entry
-                        .file_name()
-                        .into_string()
-                        .expect("parsing file name should succeed");
-
-                    if !file_name.ends_with(".toml") {
-                        panic!("unexpected file extension: {file_name}")
-                    }
-
-                    let str =
-                        fs::read_to_string(format!("/etc/sail/applications/{file_name}")).await;
-                    let str = match str {
-                        Ok(s) => s,
-                        Err(e) => {
-                            error!("failed to read `{file_name}`: {e}");
-                            continue;
-                        }
-                    };
-
-                    let config: Application = match toml::from_str(&str) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Failed to parse config file `{file_name}`: {e}");
-                            continue;
-                        }
-                    };
-
-                    apps.push(config);
+                .expect("should be able to create configuration directory"),
+        }
+
+        let backend = Backend::open(kind, &root)
+            .await
+            .expect("should be able to open the configuration backend");
+
+        let loaded = backend.load().await;
+
+        for diagnostic in loaded.diagnostics.iter() {
+            match diagnostic.is_error() {
+                true => error!("{diagnostic}"),
+                false => warn!("{diagnostic}"),
+            }
+        }
+
+        // Saving upgrades files to the current schema version, so keep the originals.
+        let mut upgrade = true;
+        if !loaded.migrated.is_empty() {
+            for (path, version) in loaded.migrated.iter() {
+                info!(
+                    "upgrading `{}` from schema version {version} to {SCHEMA_VERSION}",
+                    path.display()
+                );
+            }
+
+            let files: Vec<PathBuf> = loaded
+                .migrated
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect();
+            match backend.back_up(&files).await {
+                Ok(Some(backup)) => info!("backed up the original files to `{}`", backup.display()),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("failed to back up configuration, not upgrading the files on disk: {e}");
+                    upgrade = false;
                 }
+            }
+        }
+
+        // Saving overwrites the core configuration and removes unknown applications, so keep
+        // the entries that were skipped out of the way instead.
+        backend.set_aside(&loaded.skipped).await;
 
-                apps
+        if !loaded.diagnostics.is_empty() {
+            warn!(
+                "starting with {} problems in the configuration, see `sail status`",
+                loaded.diagnostics.len()
+            );
+        }
+
+        let secrets = SecretStore::open(&root)
+            .await
+            .expect("should be able to open the secret store");
+
+        let upload_keys = UploadKeyStore::open(&root)
+            .await
+            .expect("should be able to open the upload keys");
+
+        // Record the configuration as loaded, unless it is what was last recorded.
+        let previous = match backend.latest().await {
+            Ok(latest) => latest.map(|snapshot| snapshot.configuration),
+            Err(e) => {
+                error!("failed to read latest configuration from history: {e}");
+                None
             }
-            Err(_) => Vec::new(),
         };
 
-        let cfg = Self {
-            options: Mutex::new(Arc::new(CurrentConfiguration {
-                core: core_configuration,
-                applications,
-            })),
-        };
+        let cfg = Self::new(
+            root,
+            loaded.configuration,
+            loaded.diagnostics,
+            loaded.references,
+            backend,
+            secrets,
+            upload_keys,
+        );
+
+        if upgrade {
+            cfg.save().await;
+        }
 
-        cfg.save().await;
+        if previous.as_ref() != Some(&*cfg.get()) {
+            cfg.record(
+                &previous.unwrap_or_default(),
+                Change::new(AUTHOR, "loaded at startup"),
+            )
+            .await;
+        }
 
         cfg
     }
 
+    /// Store all of the current configuration.
     pub async fn save(&self) {
-        info!("Saving config:  {:?}", self.get());
+        if let Err(e) = self
+            .backend
+            .save(None, &self.get(), &self.references())
+            .await
+        {
+            error!("failed to save configuration: {e}")
+        }
+    }
+}
 
-        match fs::metadata("/etc/sail").await {
-            Ok(m) => {
-                if !m.is_dir() {
-                    fs::remove_file("/etc/sail").await.unwrap();
-                    fs::create_dir("/etc/sail").await.unwrap();
-                };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sail_config::events::Event;
+    use sail_core::application::Application;
+    use tempfile::TempDir;
 
-                // The directory already exists.
-            }
-            Err(_) => {
-                // The directory does not yet exist, create it.!
-                fs::create_dir("/etc/sail").await.unwrap();
-            } //
+    fn application(hostname: &str, port: u16) -> Application {
+        Application {
+            hostname: hostname.into(),
+            address: ([127, 0, 0, 1], port).into(),
         }
+    }
 
-        let cfg = self.get();
-        let core =
-            toml::to_string_pretty(&cfg.core).expect("internal config should be serializable");
+    /// A configuration kept in memory, with the secrets and upload keys in a temporary
+    /// directory that is removed with it.
+    async fn open() -> (Configuration, TempDir) {
+        let directory = TempDir::new().unwrap();
+        let root = directory.path().to_owned();
 
-        fs::write("/etc/sail/configuration.toml", core)
+        (
+            Configuration::open(root, BackendKind::Memory).await,
+            directory,
+        )
+    }
+
+    #[tokio::test]
+    async fn set() {
+        let (cfg, _directory) = open().await;
+        let mut updates = cfg.subscribe();
+
+        assert_eq!(*cfg.get(), CurrentConfiguration::default());
+        assert_eq!(cfg.backend().number().await.unwrap(), 1);
+
+        let mut new = (*cfg.get()).clone();
+        new.applications.push(application("a.example.com", 9001));
+        cfg.set(new.clone(), Change::new("test", "added a.example.com"))
             .await
             .unwrap();
 
-        match fs::metadata("/etc/sail/applications").await {
-            Ok(m) => {
-                if !m.is_dir() {
-                    fs::remove_file("/etc/sail/applications").await.unwrap();
-                    fs::create_dir("/etc/sailapplications").await.unwrap();
-                }
-            }
-            Err(_) => {
-                // does not exist, let's make the directory
-                fs::create_dir("/etc/sail/applications").await.unwrap();
-            }
-        }
+        assert_eq!(*cfg.get(), new);
+        assert_eq!(
+            cfg.routes()
+                .route("a.example.com")
+                .map(|route| route.address),
+            Some(([127, 0, 0, 1], 9001).into())
+        );
 
-        for app in cfg.applications.iter() {
-            fs::write(
-                format!("/etc/sail/applications/{}.toml", app.hostname),
-                toml::to_string_pretty(app).unwrap(),
-            )
+        let update = updates.try_recv().unwrap();
+        assert_eq!(*update.configuration, new);
+        assert_eq!(
+            update.events,
+            [Event::ApplicationAdded(application("a.example.com", 9001))]
+        );
+
+        let snapshot = cfg.backend().latest().await.unwrap().unwrap();
+        assert_eq!(snapshot.number, 2);
+        assert_eq!(snapshot.author, "test");
+        assert_eq!(snapshot.configuration, new);
+        assert_eq!(cfg.backend().entries().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unchanged() {
+        let (cfg, _directory) = open().await;
+        let mut updates = cfg.subscribe();
+
+        cfg.set((*cfg.get()).clone(), Change::new("test", "nothing"))
             .await
             .unwrap();
-        }
+
+        assert!(updates.try_recv().is_err());
     }
 }
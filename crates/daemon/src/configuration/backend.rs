@@ -0,0 +1,188 @@
+mod filesystem;
+mod memory;
+mod sqlite;
+
+use super::history::Snapshot;
+use core::fmt::{self, Display};
+use filesystem::Filesystem;
+use memory::Memory;
+use sail_config::{interpolation::References, validation::Loaded, Change, CurrentConfiguration};
+use sail_core::history::HistoryEntry;
+use sqlite::Sqlite;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The kinds of stores the configuration can be kept in, selected with `--backend`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BackendKind {
+    /// A file per application in the configuration directory.
+    #[default]
+    Filesystem,
+    /// An SQLite database in the configuration directory.
+    Sqlite,
+    /// Nothing is stored, for tests.
+    Memory,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "filesystem" => Ok(Self::Filesystem),
+            "sqlite" => Ok(Self::Sqlite),
+            "memory" => Ok(Self::Memory),
+            other => Err(other.to_string()),
+        }
+    }
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Filesystem => write!(f, "filesystem"),
+            BackendKind::Sqlite => write!(f, "sqlite"),
+            BackendKind::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+/// Where the configuration and its history are stored.
+pub enum Backend {
+    Filesystem(Filesystem),
+    Sqlite(Sqlite),
+    Memory(Memory),
+}
+
+impl Backend {
+    pub async fn open(kind: BackendKind, root: &Path) -> io::Result<Self> {
+        match kind {
+            BackendKind::Filesystem => Filesystem::open(root).await.map(Self::Filesystem),
+            BackendKind::Sqlite => Sqlite::open(root).await.map(Self::Sqlite),
+            BackendKind::Memory => Ok(Self::Memory(Memory::default())),
+        }
+    }
+
+    /// Read and validate the stored configuration.
+    pub async fn load(&self) -> Loaded {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.load().await,
+            Backend::Sqlite(sqlite) => sqlite.load().await,
+            Backend::Memory(memory) => memory.load(),
+        }
+    }
+
+    /// Store `new`, which was changed from `old`, or store all of it without `old`.
+    pub async fn save(
+        &self,
+        old: Option<&CurrentConfiguration>,
+        new: &CurrentConfiguration,
+        references: &References,
+    ) -> io::Result<()> {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.save(new, references).await,
+            Backend::Sqlite(sqlite) => sqlite.save(old, new, references).await,
+            Backend::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Record `new`, the configuration after `change`, in the history, returning its number.
+    pub async fn record(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> io::Result<u64> {
+        match self {
+            Backend::Filesystem(filesystem) => {
+                filesystem
+                    .history
+                    .record(old, new, references, change)
+                    .await
+            }
+            Backend::Sqlite(sqlite) => sqlite.record(old, new, references, change).await,
+            Backend::Memory(memory) => Ok(memory.record(old, new, references, change).await),
+        }
+    }
+
+    /// Store `new` and record it in the history, as a single transaction where the backend
+    /// supports it.
+    pub async fn commit(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> io::Result<u64> {
+        match self {
+            Backend::Sqlite(sqlite) => sqlite.commit(old, new, references, change).await,
+            _ => {
+                self.save(Some(old), new, references).await?;
+                self.record(old, new, references, change).await
+            }
+        }
+    }
+
+    pub async fn snapshot(&self, number: u64) -> io::Result<Option<Snapshot>> {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.history.snapshot(number).await,
+            Backend::Sqlite(sqlite) => sqlite.snapshot(number).await,
+            Backend::Memory(memory) => memory.snapshot(number).await,
+        }
+    }
+
+    /// Number of the latest snapshot, 0 if there is none.
+    pub async fn number(&self) -> io::Result<u64> {
+        match self {
+            Backend::Filesystem(filesystem) => Ok(filesystem.history.number().await),
+            Backend::Sqlite(sqlite) => sqlite.number().await,
+            Backend::Memory(memory) => Ok(memory.number().await),
+        }
+    }
+
+    pub async fn latest(&self) -> io::Result<Option<Snapshot>> {
+        match self.number().await? {
+            0 => Ok(None),
+            number => self.snapshot(number).await,
+        }
+    }
+
+    /// Every recorded change, oldest first.
+    pub async fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.history.entries().await,
+            Backend::Sqlite(sqlite) => sqlite.entries().await,
+            Backend::Memory(memory) => memory.entries().await,
+        }
+    }
+
+    /// Keep a copy of the stored configuration before entries in an older schema version are
+    /// upgraded, returning where.
+    pub async fn back_up(&self, migrated: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.back_up(migrated).await.map(Some),
+            Backend::Sqlite(sqlite) => sqlite.back_up().await.map(Some),
+            Backend::Memory(_) => Ok(None),
+        }
+    }
+
+    /// Move entries that could not be read out of the way, so saving doesn't overwrite them.
+    pub async fn set_aside(&self, skipped: &[PathBuf]) {
+        match self {
+            Backend::Filesystem(filesystem) => filesystem.set_aside(skipped).await,
+            Backend::Sqlite(sqlite) => sqlite.set_aside(skipped).await,
+            Backend::Memory(_) => {}
+        }
+    }
+
+    /// The directory with files that can be edited by hand, which is watched for changes.
+    pub fn watched(&self) -> Option<&Path> {
+        match self {
+            Backend::Filesystem(filesystem) => Some(filesystem.root()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use super::super::{history::History, persistence};
+use sail_config::{
+    interpolation::References,
+    schema,
+    validation::{self, Loaded},
+    CurrentConfiguration, APPLICATIONS_DIRECTORY, CORE_FILE,
+};
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+use tracing::error;
+
+/// The configuration as a core file and a file per application, which can be edited by hand.
+pub struct Filesystem {
+    root: PathBuf,
+    pub history: History,
+}
+
+impl Filesystem {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        Ok(Self {
+            root: root.to_owned(),
+            history: History::open(root).await?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Read and validate the configuration files, off the async runtime.
+    pub async fn load(&self) -> Loaded {
+        let root = self.root.clone();
+
+        tokio::task::spawn_blocking(move || validation::load(&root))
+            .await
+            .expect("loading configuration should not panic")
+    }
+
+    /// Write the configuration to disk, replacing each file atomically and removing the files of
+    /// applications that no longer exist.
+    pub async fn save(
+        &self,
+        cfg: &CurrentConfiguration,
+        references: &References,
+    ) -> io::Result<()> {
+        create_directory(&self.root).await?;
+
+        let core = schema::write(&references.core_table(&cfg.core));
+
+        persistence::write_atomically(&self.root.join(CORE_FILE), core.as_bytes()).await?;
+
+        let applications = self.root.join(APPLICATIONS_DIRECTORY);
+        create_directory(&applications).await?;
+
+        let mut expected = HashSet::new();
+
+        for app in cfg.applications.iter() {
+            let file_name = format!("{}.toml", app.hostname);
+            let content = schema::write(&references.application_table(app));
+
+            persistence::write_atomically(&applications.join(&file_name), content.as_bytes())
+                .await?;
+
+            expected.insert(file_name.into());
+        }
+
+        persistence::reconcile(&applications, &expected).await
+    }
+
+    /// Copy the files that are about to be upgraded to a backup directory.
+    pub async fn back_up(&self, migrated: &[PathBuf]) -> io::Result<PathBuf> {
+        persistence::back_up(&self.root, migrated.iter().map(PathBuf::as_path)).await
+    }
+
+    /// Rename the files that were skipped, since saving would overwrite or remove them.
+    pub async fn set_aside(&self, skipped: &[PathBuf]) {
+        for path in skipped {
+            match persistence::set_aside(path).await {
+                Ok(moved) => error!("moved `{}` to `{}`", path.display(), moved.display()),
+                Err(e) => error!("failed to move `{}` aside: {e}", path.display()),
+            }
+        }
+    }
+}
+
+/// Create `directory` if it does not exist yet. A file in its place is left alone, it may be
+/// a mistyped `--config-dir`.
+async fn create_directory(directory: &Path) -> io::Result<()> {
+    match fs::metadata(directory).await {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("`{}` is not a directory", directory.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir_all(directory).await,
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,67 @@
+use super::super::history::{Snapshot, MAX_SNAPSHOTS};
+use sail_config::{interpolation::References, validation::Loaded, Change, CurrentConfiguration};
+use sail_core::history::HistoryEntry;
+use std::{collections::BTreeMap, io};
+use tokio::sync::Mutex;
+
+/// Keeps the history in memory and nothing else, every start is a fresh configuration.
+#[derive(Default)]
+pub struct Memory {
+    /// Written snapshots by number, so they go through the same serialization as stored ones.
+    history: Mutex<BTreeMap<u64, String>>,
+}
+
+impl Memory {
+    pub fn load(&self) -> Loaded {
+        Loaded {
+            configuration: CurrentConfiguration::default(),
+            diagnostics: Vec::new(),
+            skipped: Vec::new(),
+            migrated: Vec::new(),
+            references: References::default(),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> u64 {
+        let mut history = self.history.lock().await;
+        let number = history.last_key_value().map_or(0, |(number, _)| *number) + 1;
+
+        let snapshot = Snapshot::new(number, old, new, references, change);
+        history.insert(number, snapshot.write());
+
+        let oldest = number.saturating_sub(MAX_SNAPSHOTS);
+        history.retain(|number, _| *number > oldest);
+
+        number
+    }
+
+    pub async fn snapshot(&self, number: u64) -> io::Result<Option<Snapshot>> {
+        match self.history.lock().await.get(&number) {
+            Some(content) => Snapshot::read(content).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn number(&self) -> u64 {
+        self.history
+            .lock()
+            .await
+            .last_key_value()
+            .map_or(0, |(number, _)| *number)
+    }
+
+    pub async fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        self.history
+            .lock()
+            .await
+            .values()
+            .map(|content| Snapshot::entry(content))
+            .collect()
+    }
+}
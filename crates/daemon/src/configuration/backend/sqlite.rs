@@ -0,0 +1,495 @@
+use super::super::{
+    history::{Snapshot, MAX_SNAPSHOTS},
+    persistence,
+};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use sail_config::{
+    interpolation::References,
+    schema::{self, Kind},
+    validation::{self, Loaded},
+    Change, CoreConfiguration, CurrentConfiguration, APPLICATIONS_DIRECTORY, CORE_FILE,
+};
+use sail_core::{application::Application, diagnostic::Diagnostic, history::HistoryEntry};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+/// File inside the configuration directory with the database.
+pub const DATABASE_FILE: &str = "sail.db";
+
+/// Every migration of the database, in order. The number applied is kept in `user_version`.
+const MIGRATIONS: &[&str] = &[
+    // The core configuration and the applications are stored like their files, so they are
+    // migrated and validated the same way.
+    "CREATE TABLE core (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        content TEXT NOT NULL
+    );
+    CREATE TABLE applications (
+        hostname TEXT PRIMARY KEY,
+        content TEXT NOT NULL
+    );
+    CREATE TABLE history (
+        number INTEGER PRIMARY KEY,
+        content TEXT NOT NULL
+    );
+    CREATE TABLE invalid (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        content TEXT NOT NULL,
+        time INTEGER NOT NULL
+    );",
+];
+
+/// The configuration and its history in an SQLite database, where a change and its history
+/// entry are written in a single transaction.
+pub struct Sqlite {
+    path: PathBuf,
+    root: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Sqlite {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        let path = root.join(DATABASE_FILE);
+        let open = path.clone();
+
+        let connection = tokio::task::spawn_blocking(move || {
+            let mut connection = Connection::open(&open).map_err(to_io)?;
+            migrate(&mut connection)?;
+            Ok::<_, io::Error>(connection)
+        })
+        .await
+        .expect("opening the database should not panic")?;
+
+        Ok(Self {
+            path,
+            root: root.to_owned(),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Run `f` with the connection, off the async runtime.
+    async fn run<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .expect("database lock should not be poisoned");
+            f(&mut connection)
+        })
+        .await
+        .expect("database queries should not panic")
+        .map_err(to_io)
+    }
+
+    pub async fn load(&self) -> Loaded {
+        let rows = self
+            .run(|connection| {
+                let core: Option<String> = connection
+                    .query_row("SELECT content FROM core WHERE id = 1", [], |row| {
+                        row.get(0)
+                    })
+                    .optional()?;
+
+                let applications = connection
+                    .prepare("SELECT hostname, content FROM applications ORDER BY hostname")?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+
+                Ok((core, applications))
+            })
+            .await;
+
+        let mut loaded = Loaded {
+            configuration: CurrentConfiguration::default(),
+            diagnostics: Vec::new(),
+            skipped: Vec::new(),
+            migrated: Vec::new(),
+            references: References::default(),
+        };
+
+        let (core, applications) = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                loaded.diagnostics.push(
+                    Diagnostic::error(format!("cannot read database: {e}")).in_file(DATABASE_FILE),
+                );
+                return loaded;
+            }
+        };
+
+        if let Some(content) = core {
+            match schema::read::<CoreConfiguration>(&content, Kind::Core) {
+                Ok(core) => {
+                    if core.migrated() {
+                        loaded.migrated.push((CORE_FILE.into(), core.version));
+                    }
+                    loaded.references.set_core(core.references);
+                    loaded.configuration.core = core.value;
+                }
+                Err(e) => {
+                    loaded.diagnostics.push(validation::schema_error(
+                        CORE_FILE.into(),
+                        &content,
+                        &e,
+                    ));
+                    loaded.skipped.push(CORE_FILE.into());
+                }
+            }
+        }
+
+        for (hostname, content) in applications {
+            let name = application_name(&hostname);
+
+            match schema::read::<Application>(&content, Kind::Application) {
+                Ok(application) => {
+                    if application.migrated() {
+                        loaded.migrated.push((name.into(), application.version));
+                    }
+                    loaded
+                        .references
+                        .set_application(&application.value.hostname, application.references);
+                    loaded.configuration.applications.push(application.value);
+                }
+                Err(e) => {
+                    loaded
+                        .diagnostics
+                        .push(validation::schema_error(name.clone(), &content, &e));
+                    loaded.skipped.push(name.into());
+                }
+            }
+        }
+
+        loaded
+            .diagnostics
+            .extend(validation::validate(&loaded.configuration));
+
+        loaded
+    }
+
+    /// Write `new`, only the rows that changed from `old`, or every row without `old`.
+    pub async fn save(
+        &self,
+        old: Option<&CurrentConfiguration>,
+        new: &CurrentConfiguration,
+        references: &References,
+    ) -> io::Result<()> {
+        let rows = Rows::new(old, new, references);
+
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            rows.write(&transaction)?;
+            transaction.commit()
+        })
+        .await
+    }
+
+    pub async fn record(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> io::Result<u64> {
+        let (old, new, references) = (old.clone(), new.clone(), references.clone());
+
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            let number = insert_snapshot(&transaction, &old, &new, &references, change)?;
+            transaction.commit()?;
+            Ok(number)
+        })
+        .await
+    }
+
+    /// Write `new` and its history entry in one transaction, so neither is stored without the
+    /// other.
+    pub async fn commit(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> io::Result<u64> {
+        let rows = Rows::new(Some(old), new, references);
+        let (old, new, references) = (old.clone(), new.clone(), references.clone());
+
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            rows.write(&transaction)?;
+            let number = insert_snapshot(&transaction, &old, &new, &references, change)?;
+            transaction.commit()?;
+            Ok(number)
+        })
+        .await
+    }
+
+    pub async fn snapshot(&self, number: u64) -> io::Result<Option<Snapshot>> {
+        let content: Option<String> = self
+            .run(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT content FROM history WHERE number = ?1",
+                        [number as i64],
+                        |row| row.get(0),
+                    )
+                    .optional()
+            })
+            .await?;
+
+        content.map(|content| Snapshot::read(&content)).transpose()
+    }
+
+    pub async fn number(&self) -> io::Result<u64> {
+        self.run(|connection| latest(connection)).await
+    }
+
+    pub async fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        let contents = self
+            .run(|connection| {
+                connection
+                    .prepare("SELECT content FROM history ORDER BY number")?
+                    .query_map([], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()
+            })
+            .await?;
+
+        contents
+            .iter()
+            .map(|content| Snapshot::entry(content))
+            .collect()
+    }
+
+    /// Copy the database to a backup directory.
+    pub async fn back_up(&self) -> io::Result<PathBuf> {
+        persistence::back_up(&self.root, [self.path.as_path()]).await
+    }
+
+    /// Move the rows that were skipped to the `invalid` table, since saving would overwrite or
+    /// remove them.
+    pub async fn set_aside(&self, skipped: &[PathBuf]) {
+        for name in skipped {
+            let name = name.to_string_lossy().into_owned();
+            let moved = name.clone();
+
+            let result = self
+                .run(move |connection| {
+                    let transaction = connection.transaction()?;
+
+                    let (select, delete, key) = match moved.as_str() {
+                        CORE_FILE => (
+                            "SELECT content FROM core WHERE id = ?1",
+                            "DELETE FROM core WHERE id = ?1",
+                            "1".to_owned(),
+                        ),
+                        other => (
+                            "SELECT content FROM applications WHERE hostname = ?1",
+                            "DELETE FROM applications WHERE hostname = ?1",
+                            hostname(other).to_owned(),
+                        ),
+                    };
+
+                    let content: String =
+                        transaction.query_row(select, [&key], |row| row.get(0))?;
+                    transaction.execute(
+                        "INSERT INTO invalid (name, content, time) VALUES (?1, ?2, ?3)",
+                        params![moved, content, now() as i64],
+                    )?;
+                    transaction.execute(delete, [&key])?;
+
+                    transaction.commit()
+                })
+                .await;
+
+            match result {
+                Ok(()) => error!("moved `{name}` to the `invalid` table of the database"),
+                Err(e) => error!("failed to move `{name}` aside: {e}"),
+            }
+        }
+    }
+}
+
+/// The rows to write for a change from `old` to `new`.
+struct Rows {
+    core: String,
+    /// Hostnames and contents of new or changed applications.
+    upsert: Vec<(String, String)>,
+    /// Hostnames of applications to delete, or `None` to delete every other application.
+    delete: Option<Vec<String>>,
+}
+
+impl Rows {
+    fn new(
+        old: Option<&CurrentConfiguration>,
+        new: &CurrentConfiguration,
+        references: &References,
+    ) -> Self {
+        let unchanged = |application: &Application| {
+            old.is_some_and(|old| old.applications.contains(application))
+        };
+
+        let upsert = new
+            .applications
+            .iter()
+            .filter(|application| !unchanged(application))
+            .map(|application| {
+                (
+                    application.hostname.clone(),
+                    schema::write(&references.application_table(application)),
+                )
+            })
+            .collect();
+
+        let delete = old.map(|old| {
+            old.applications
+                .iter()
+                .filter(|a| !new.applications.iter().any(|b| a.hostname == b.hostname))
+                .map(|a| a.hostname.clone())
+                .collect()
+        });
+
+        Self {
+            core: schema::write(&references.core_table(&new.core)),
+            upsert,
+            delete,
+        }
+    }
+
+    fn write(&self, transaction: &Transaction) -> rusqlite::Result<()> {
+        transaction.execute(
+            "INSERT INTO core (id, content) VALUES (1, ?1)
+             ON CONFLICT (id) DO UPDATE SET content = excluded.content",
+            [&self.core],
+        )?;
+
+        for (hostname, content) in self.upsert.iter() {
+            transaction.execute(
+                "INSERT INTO applications (hostname, content) VALUES (?1, ?2)
+                 ON CONFLICT (hostname) DO UPDATE SET content = excluded.content",
+                [hostname, content],
+            )?;
+        }
+
+        match &self.delete {
+            Some(hostnames) => {
+                for hostname in hostnames {
+                    transaction
+                        .execute("DELETE FROM applications WHERE hostname = ?1", [hostname])?;
+                }
+            }
+            None => {
+                let mut keep = transaction.prepare("SELECT hostname FROM applications")?;
+                let stored = keep
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                for hostname in stored {
+                    if !self.upsert.iter().any(|(h, _)| *h == hostname) {
+                        info!("removing application `{hostname}` from the database");
+                        transaction
+                            .execute("DELETE FROM applications WHERE hostname = ?1", [hostname])?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Store a snapshot of `new` with the next number and remove the ones that are too old.
+fn insert_snapshot(
+    transaction: &Transaction,
+    old: &CurrentConfiguration,
+    new: &CurrentConfiguration,
+    references: &References,
+    change: Change,
+) -> rusqlite::Result<u64> {
+    let number = latest(transaction)? + 1;
+    let snapshot = Snapshot::new(number, old, new, references, change);
+
+    transaction.execute(
+        "INSERT INTO history (number, content) VALUES (?1, ?2)",
+        params![number as i64, snapshot.write()],
+    )?;
+    transaction.execute(
+        "DELETE FROM history WHERE number <= ?1",
+        [number.saturating_sub(MAX_SNAPSHOTS) as i64],
+    )?;
+
+    info!(
+        "recorded configuration {number}: {} by {}",
+        snapshot.description, snapshot.author
+    );
+
+    Ok(number)
+}
+
+/// Number of the latest snapshot, 0 if there is none.
+fn latest(connection: &Connection) -> rusqlite::Result<u64> {
+    connection.query_row("SELECT COALESCE(MAX(number), 0) FROM history", [], |row| {
+        row.get::<_, i64>(0).map(|number| number as u64)
+    })
+}
+
+/// Apply the migrations the database doesn't have yet.
+fn migrate(connection: &mut Connection) -> io::Result<()> {
+    let transaction = connection.transaction().map_err(to_io)?;
+    let version: i64 = transaction
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(to_io)?;
+    let version = version as usize;
+
+    if version > MIGRATIONS.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "database version {version} is newer than this version of Sail supports ({})",
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        transaction.execute_batch(migration).map_err(to_io)?;
+        transaction
+            .pragma_update(None, "user_version", (index + 1) as i64)
+            .map_err(to_io)?;
+        info!("migrated database to version {}", index + 1);
+    }
+
+    transaction.commit().map_err(to_io)
+}
+
+/// Name an application row is reported with, like the file it would be saved in.
+fn application_name(hostname: &str) -> String {
+    format!("{APPLICATIONS_DIRECTORY}/{hostname}.toml")
+}
+
+/// The hostname of a row named by [`application_name`].
+fn hostname(name: &str) -> &str {
+    name.strip_prefix(&format!("{APPLICATIONS_DIRECTORY}/"))
+        .and_then(|name| name.strip_suffix(".toml"))
+        .unwrap_or(name)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn to_io(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
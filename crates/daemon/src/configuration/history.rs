@@ -0,0 +1,218 @@
+use super::persistence;
+use sail_config::{interpolation::References, Change, CurrentConfiguration};
+use sail_core::history::HistoryEntry;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, sync::Mutex};
+use toml::{Table, Value};
+use tracing::info;
+
+/// Directory inside the configuration directory where snapshots are kept.
+pub const HISTORY_DIRECTORY: &str = "history";
+
+/// Number of snapshots to keep, older ones are removed.
+pub const MAX_SNAPSHOTS: u64 = 1000;
+
+/// The configuration after a change, with who made the change and when.
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub number: u64,
+    pub time: u64,
+    pub author: String,
+    pub description: String,
+    pub changes: Vec<String>,
+    pub configuration: CurrentConfiguration,
+    /// Values in the configuration that were written as references, which are stored as
+    /// references too.
+    #[serde(skip)]
+    pub references: References,
+}
+
+impl Snapshot {
+    /// A snapshot of `new`, the configuration after `change`.
+    pub fn new(
+        number: u64,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> Self {
+        Self {
+            number,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            author: change.author,
+            description: change.description,
+            changes: old.changes(new),
+            configuration: new.clone(),
+            references: references.clone(),
+        }
+    }
+
+    /// Serialize the snapshot, with the references in the configuration restored.
+    pub fn write(&self) -> String {
+        let mut table = Table::try_from(self).expect("snapshot should be serializable");
+        table.insert(
+            "configuration".into(),
+            Value::Table(self.references.configuration_table(&self.configuration)),
+        );
+
+        toml::to_string_pretty(&table).expect("snapshot should be serializable")
+    }
+
+    /// Deserialize a snapshot written by [`Snapshot::write`], resolving its references.
+    pub fn read(content: &str) -> io::Result<Self> {
+        let mut table: Table =
+            toml::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let references = match table.get_mut("configuration") {
+            Some(Value::Table(configuration)) => {
+                References::interpolate_configuration(configuration)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            }
+            _ => References::default(),
+        };
+
+        let snapshot: Snapshot = Value::Table(table)
+            .try_into()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Snapshot {
+            references,
+            ..snapshot
+        })
+    }
+
+    /// Read only the description of a snapshot written by [`Snapshot::write`], so the
+    /// references in the configuration aren't resolved.
+    pub fn entry(content: &str) -> io::Result<HistoryEntry> {
+        toml::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Numbered snapshots of every committed configuration, stored as `history/<number>.toml`.
+pub struct History {
+    directory: PathBuf,
+    /// Number of the latest snapshot, 0 if there is none. Held while recording, so numbers are
+    /// handed out in order.
+    latest: Mutex<u64>,
+}
+
+impl History {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        let directory = root.join(HISTORY_DIRECTORY);
+        fs::create_dir_all(&directory).await?;
+
+        let numbers = numbers(&directory).await?;
+        let latest = numbers.last().copied().unwrap_or(0);
+
+        let history = Self {
+            directory,
+            latest: Mutex::new(latest),
+        };
+
+        history.prune(latest).await?;
+
+        Ok(history)
+    }
+
+    /// Store a snapshot of `new`, the configuration after `change`, returning its number.
+    pub async fn record(
+        &self,
+        old: &CurrentConfiguration,
+        new: &CurrentConfiguration,
+        references: &References,
+        change: Change,
+    ) -> io::Result<u64> {
+        let mut latest = self.latest.lock().await;
+        let number = *latest + 1;
+
+        let snapshot = Snapshot::new(number, old, new, references, change);
+        persistence::write_atomically(&self.path(number), snapshot.write().as_bytes()).await?;
+
+        *latest = number;
+
+        info!(
+            "recorded configuration {number}: {} by {}",
+            snapshot.description, snapshot.author
+        );
+
+        self.prune(number).await?;
+
+        Ok(number)
+    }
+
+    pub async fn snapshot(&self, number: u64) -> io::Result<Option<Snapshot>> {
+        let content = match fs::read_to_string(self.path(number)).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Snapshot::read(&content).map(Some)
+    }
+
+    /// Number of the latest snapshot, 0 if there is none.
+    pub async fn number(&self) -> u64 {
+        *self.latest.lock().await
+    }
+
+    /// Every recorded change, oldest first.
+    pub async fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+
+        for number in numbers(&self.directory).await? {
+            let content = fs::read_to_string(self.path(number)).await?;
+            entries.push(Snapshot::entry(&content)?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove snapshots that are more than `MAX_SNAPSHOTS` older than `latest`.
+    async fn prune(&self, latest: u64) -> io::Result<()> {
+        let oldest = latest.saturating_sub(MAX_SNAPSHOTS);
+
+        for number in numbers(&self.directory).await? {
+            if number > oldest {
+                break;
+            }
+
+            fs::remove_file(self.path(number)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, number: u64) -> PathBuf {
+        self.directory.join(format!("{number}.toml"))
+    }
+}
+
+/// Numbers of the snapshots in `directory`, in order.
+async fn numbers(directory: &Path) -> io::Result<Vec<u64>> {
+    let mut entries = fs::read_dir(directory).await?;
+    let mut numbers = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let number = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".toml"))
+            .and_then(|number| number.parse().ok());
+
+        if let Some(number) = number {
+            numbers.push(number);
+        }
+    }
+
+    numbers.sort_unstable();
+
+    Ok(numbers)
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Suffix of the temporary file a configuration file is written to before it replaces the
+/// original.
+const TEMPORARY_SUFFIX: &str = ".tmp";
+
+/// Suffix of a configuration file that was set aside because it could not be parsed.
+const INVALID_SUFFIX: &str = ".invalid";
+
+/// Directory inside the configuration directory where files are backed up before they are
+/// upgraded to a newer schema version.
+pub const BACKUPS_DIRECTORY: &str = "backups";
+
+/// Replace the file at `path` with `contents`, such that after a crash the file either has its
+/// previous or its new contents, never a mix or a truncated version.
+///
+/// The contents are written to a hidden temporary file in the same directory, synced to disk and
+/// renamed over the original, after which the directory itself is synced so the rename is durable.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write(path, contents, 0o666).await
+}
+
+/// Like [`write_atomically`], but the file is only readable by its owner, for files with
+/// secrets like private keys.
+pub async fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write(path, contents, 0o600).await
+}
+
+async fn write(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(TEMPORARY_SUFFIX);
+    let temporary = directory.join(temporary_name);
+
+    let result = async {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&temporary)
+            .await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        fs::rename(&temporary, path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary).await;
+    }
+    result?;
+
+    sync_directory(directory).await
+}
+
+/// Remove every file in `directory` that is not in `expected`, including temporary files left
+/// behind by an interrupted save. Other hidden files, like the swap files of editors, are kept.
+pub async fn reconcile(directory: &Path, expected: &HashSet<OsString>) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory).await?;
+    let mut removed = false;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+
+        let temporary = file_name
+            .as_encoded_bytes()
+            .ends_with(TEMPORARY_SUFFIX.as_bytes());
+
+        if expected.contains(&file_name)
+            || (file_name.as_encoded_bytes().starts_with(b".") && !temporary)
+        {
+            continue;
+        }
+
+        if entry.file_type().await?.is_dir() {
+            warn!(
+                "not removing unexpected directory `{}`",
+                entry.path().display()
+            );
+            continue;
+        }
+
+        info!("removing `{}`", entry.path().display());
+
+        fs::remove_file(entry.path()).await?;
+        removed = true;
+    }
+
+    if removed {
+        sync_directory(directory).await?;
+    }
+
+    Ok(())
+}
+
+/// Rename a file that could not be parsed to a hidden file next to it, so it is kept for
+/// inspection but no longer read as configuration.
+pub async fn set_aside(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let mut invalid_name = OsString::from(".");
+    invalid_name.push(file_name);
+    invalid_name.push(INVALID_SUFFIX);
+    let invalid = path.with_file_name(invalid_name);
+
+    fs::rename(path, &invalid).await?;
+
+    Ok(invalid)
+}
+
+/// Copy `files` inside `root` to a new directory in `root/backups`, keeping their paths relative
+/// to `root`, and return the path of that directory.
+pub async fn back_up(root: &Path, files: impl IntoIterator<Item = &Path>) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let backups = root.join(BACKUPS_DIRECTORY);
+    fs::create_dir_all(&backups).await?;
+
+    // Never mix files into an existing backup.
+    let mut backup = backups.join(timestamp.to_string());
+    let mut attempt = 1;
+    while fs::try_exists(&backup).await? {
+        backup = backups.join(format!("{timestamp}-{attempt}"));
+        attempt += 1;
+    }
+
+    for file in files {
+        let relative = file.strip_prefix(root).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is outside of `{}`", file.display(), root.display()),
+            )
+        })?;
+        let destination = backup.join(relative);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::copy(file, &destination).await?;
+        File::open(&destination).await?.sync_all().await?;
+    }
+
+    Ok(backup)
+}
+
+async fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory).await?.sync_all().await
+}
@@ -0,0 +1,278 @@
+use super::Configuration;
+use core::fmt::{self, Display};
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
+};
+use sail_config::{Change, Configurable, APPLICATIONS_DIRECTORY, CORE_FILE};
+use sail_core::diagnostic::Diagnostic;
+use std::{
+    error::Error,
+    io,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    io::unix::AsyncFd,
+    select,
+    signal::unix::{signal, SignalKind},
+    time::sleep,
+};
+use tracing::{error, info, warn};
+
+/// Time to wait for a burst of file system events (like an editor saving a file) to settle.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Reload the configuration on SIGHUP and whenever a file in the configuration directory
+/// changes, until SIGTERM is received.
+pub async fn watch(config: Arc<Configuration>) {
+    let mut sighup = signal(SignalKind::hangup()).unwrap();
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+
+    // Only files can be edited by hand, other backends are changed through the daemon.
+    let watcher = match config.backend().watched().map(Watcher::new) {
+        Some(Ok(watcher)) => Some(watcher),
+        Some(Err(e)) => {
+            error!("failed to watch configuration directory, only reloading on SIGHUP: {e}");
+            None
+        }
+        None => None,
+    };
+
+    loop {
+        select! {
+            _ = sigterm.recv() => break,
+            _ = sighup.recv() => {
+                info!("received SIGHUP signal, reloading configuration");
+            },
+            Ok(()) = async {
+                match &watcher {
+                    Some(watcher) => watcher.changed().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                sleep(DEBOUNCE).await;
+
+                if let Some(watcher) = &watcher {
+                    watcher.drain();
+                }
+
+                info!("configuration files changed, reloading configuration");
+            }
+        }
+
+        if let Err(e) = config.reload().await {
+            error!("keeping current configuration, reloading failed: {e}")
+        }
+    }
+}
+
+impl Configuration {
+    /// Re-read the stored configuration and swap it in, keeping the current configuration if
+    /// the new one cannot be read or is invalid.
+    pub async fn reload(&self) -> Result<(), ReloadError> {
+        let _changing = self.lock().await;
+
+        let loaded = self.backend.load().await;
+
+        for diagnostic in loaded.diagnostics.iter().filter(|d| !d.is_error()) {
+            warn!("{diagnostic}");
+        }
+
+        // Report the problems of what is on disk, even when it is not applied.
+        self.problems.store(Arc::new(loaded.diagnostics.clone()));
+
+        if loaded.has_errors() {
+            return Err(ReloadError::Invalid(
+                loaded
+                    .diagnostics
+                    .into_iter()
+                    .filter(Diagnostic::is_error)
+                    .collect(),
+            ));
+        }
+
+        // What is on disk is the source of the references, even if no value changed.
+        self.references.store(Arc::new(loaded.references));
+
+        let new = loaded.configuration;
+
+        let old = self.get();
+        let changes = old.changes(&new);
+
+        if changes.is_empty() {
+            info!("configuration unchanged");
+            return Ok(());
+        }
+
+        for change in changes.iter() {
+            info!("reloaded configuration: {change}");
+        }
+
+        if old.core.metrics_port != new.core.metrics_port {
+            warn!("metrics port changes take effect after a restart");
+        }
+
+        self.replace(new);
+        self.record(&old, Change::new(super::AUTHOR, "reloaded from disk"))
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Inotify watch on the configuration files.
+struct Watcher {
+    inotify: AsyncFd<InotifyFd>,
+    root: PathBuf,
+    /// Watch on the applications directory, while it exists.
+    applications: Mutex<Option<WatchDescriptor>>,
+}
+
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Events of a changed file, or of the applications directory coming or going.
+const FLAGS: AddWatchFlags = AddWatchFlags::IN_CLOSE_WRITE
+    .union(AddWatchFlags::IN_CREATE)
+    .union(AddWatchFlags::IN_DELETE)
+    .union(AddWatchFlags::IN_MOVED_FROM)
+    .union(AddWatchFlags::IN_MOVED_TO);
+
+impl Watcher {
+    fn new(root: &Path) -> io::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(root, FLAGS)?;
+
+        let watcher = Self {
+            inotify: AsyncFd::new(InotifyFd(inotify))?,
+            root: root.to_owned(),
+            applications: Mutex::new(None),
+        };
+
+        // The applications directory only exists once the configuration has been saved, it is
+        // watched when it is created.
+        if let Err(e) = watcher.watch_applications() {
+            if e != Errno::ENOENT {
+                warn!("not watching `{APPLICATIONS_DIRECTORY}`: {e}");
+            }
+        }
+
+        Ok(watcher)
+    }
+
+    fn watch_applications(&self) -> nix::Result<()> {
+        let applications = self.root.join(APPLICATIONS_DIRECTORY);
+        let descriptor = self.inotify.get_ref().0.add_watch(&applications, FLAGS)?;
+
+        *self.applications() = Some(descriptor);
+
+        Ok(())
+    }
+
+    fn applications(&self) -> MutexGuard<'_, Option<WatchDescriptor>> {
+        self.applications
+            .lock()
+            .expect("should be able to get lock on the applications watch")
+    }
+
+    /// Wait until a configuration file changed. The daemon writes other files in the
+    /// directory, like the secrets and the history, which are ignored.
+    async fn changed(&self) -> io::Result<()> {
+        loop {
+            let mut guard = self.inotify.readable().await?;
+            guard.clear_ready();
+
+            if self.drain() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle all pending events, returning whether a configuration file changed.
+    fn drain(&self) -> bool {
+        let mut changed = false;
+
+        while let Ok(events) = self.inotify.get_ref().0.read_events() {
+            if events.is_empty() {
+                break;
+            }
+
+            for event in events {
+                changed |= self.handle(event);
+            }
+        }
+
+        changed
+    }
+
+    fn handle(&self, event: InotifyEvent) -> bool {
+        // Events were lost, any file may have changed.
+        if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+            return true;
+        }
+
+        let applications = *self.applications();
+
+        // The applications directory was removed.
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            if applications == Some(event.wd) {
+                *self.applications() = None;
+            }
+            return false;
+        }
+
+        let Some(name) = event.name else {
+            return false;
+        };
+        let name = name.to_string_lossy();
+
+        if applications == Some(event.wd) {
+            return !name.starts_with('.') && name.ends_with(".toml");
+        }
+
+        if name == APPLICATIONS_DIRECTORY {
+            if event
+                .mask
+                .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+            {
+                if let Err(e) = self.watch_applications() {
+                    error!("not watching `{APPLICATIONS_DIRECTORY}`, changes to it are only reloaded on SIGHUP: {e}");
+                }
+            }
+            return true;
+        }
+
+        name == CORE_FILE
+    }
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    Invalid(Vec<Diagnostic>),
+}
+
+impl Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Invalid(diagnostics) => {
+                write!(f, "invalid configuration")?;
+
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {diagnostic}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ReloadError {}
@@ -0,0 +1,477 @@
+use super::persistence;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, Generate, Key, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use core::fmt::{self, Display};
+use sail_config::{export::Secrets, interpolation::CREDENTIALS_DIRECTORY_VARIABLE};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, sync::Mutex};
+use tracing::{info, warn};
+
+/// File inside the configuration directory with the encrypted secrets.
+pub const SECRETS_FILE: &str = "secrets.toml";
+
+/// File inside the configuration directory with the key the secrets are encrypted with, unless
+/// it is passed as a systemd credential.
+pub const KEY_FILE: &str = "secrets.key";
+
+/// Name of the systemd credential with the key the secrets are encrypted with.
+pub const KEY_CREDENTIAL: &str = "secrets-key";
+
+/// Maximum length of the name of a secret.
+const MAX_NAME_LENGTH: usize = 64;
+
+const NONCE_LENGTH: usize = 24;
+
+/// The encrypted secrets of every application, as stored in [`SECRETS_FILE`].
+#[derive(Default, Deserialize, Serialize)]
+struct Stored {
+    /// Secrets by name, by hostname of the application. Values are the nonce followed by the
+    /// ciphertext, base64 encoded.
+    #[serde(default)]
+    applications: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Named secrets of applications, encrypted at rest.
+pub struct SecretStore {
+    path: PathBuf,
+    cipher: XChaCha20Poly1305,
+    stored: Mutex<Stored>,
+}
+
+impl SecretStore {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        let cipher = cipher(root).await?;
+        let path = root.join(SECRETS_FILE);
+
+        let stored = match fs::read_to_string(&path).await {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            cipher,
+            stored: Mutex::new(stored),
+        })
+    }
+
+    pub async fn set(&self, hostname: &str, name: &str, value: &str) -> Result<(), SecretError> {
+        check_name(name)?;
+        let encrypted = self.encrypt(hostname, name, value)?;
+
+        let mut stored = self.stored.lock().await;
+        stored
+            .applications
+            .entry(hostname.to_owned())
+            .or_default()
+            .insert(name.to_owned(), encrypted);
+
+        self.write(&stored).await.map_err(SecretError::Io)
+    }
+
+    pub async fn get(&self, hostname: &str, name: &str) -> Result<String, SecretError> {
+        let stored = self.stored.lock().await;
+        let encrypted = stored
+            .applications
+            .get(hostname)
+            .and_then(|secrets| secrets.get(name))
+            .ok_or(SecretError::NotFound)?;
+
+        self.decrypt(hostname, name, encrypted)
+    }
+
+    /// Every secret of the applications in `hostnames`, decrypted, to export them.
+    pub async fn export(&self, hostnames: &[&str]) -> Result<Secrets, SecretError> {
+        let stored = self.stored.lock().await;
+        let mut exported = Secrets::new();
+
+        for (hostname, secrets) in stored.applications.iter() {
+            if !hostnames.contains(&hostname.as_str()) {
+                continue;
+            }
+
+            for (name, encrypted) in secrets.iter() {
+                exported
+                    .entry(hostname.clone())
+                    .or_default()
+                    .insert(name.clone(), self.decrypt(hostname, name, encrypted)?);
+            }
+        }
+
+        Ok(exported)
+    }
+
+    /// Set all of `secrets` at once, keeping the other secrets.
+    pub async fn import(&self, secrets: &Secrets) -> Result<(), SecretError> {
+        let mut encrypted = Vec::new();
+        for (hostname, secrets) in secrets.iter() {
+            for (name, value) in secrets.iter() {
+                check_name(name)?;
+                encrypted.push((hostname, name, self.encrypt(hostname, name, value)?));
+            }
+        }
+
+        if encrypted.is_empty() {
+            return Ok(());
+        }
+
+        let mut stored = self.stored.lock().await;
+        for (hostname, name, value) in encrypted {
+            stored
+                .applications
+                .entry(hostname.clone())
+                .or_default()
+                .insert(name.clone(), value);
+        }
+
+        self.write(&stored).await.map_err(SecretError::Io)
+    }
+
+    /// The nonce followed by the ciphertext of `value`, base64 encoded.
+    fn encrypt(&self, hostname: &str, name: &str, value: &str) -> Result<String, SecretError> {
+        let nonce = XNonce::generate();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: &associated_data(hostname, name),
+                },
+            )
+            .map_err(|_| SecretError::Encryption)?;
+
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(ciphertext);
+
+        Ok(STANDARD.encode(encrypted))
+    }
+
+    fn decrypt(&self, hostname: &str, name: &str, encoded: &str) -> Result<String, SecretError> {
+        let encrypted = STANDARD
+            .decode(encoded)
+            .map_err(|_| SecretError::Decryption)?;
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(SecretError::Decryption);
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let nonce = XNonce::try_from(nonce).map_err(|_| SecretError::Decryption)?;
+
+        let value = self
+            .cipher
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: &associated_data(hostname, name),
+                },
+            )
+            .map_err(|_| SecretError::Decryption)?;
+
+        String::from_utf8(value).map_err(|_| SecretError::Decryption)
+    }
+
+    /// Names of the secrets of an application.
+    pub async fn list(&self, hostname: &str) -> Vec<String> {
+        self.stored
+            .lock()
+            .await
+            .applications
+            .get(hostname)
+            .map(|secrets| secrets.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn delete(&self, hostname: &str, name: &str) -> Result<(), SecretError> {
+        let mut stored = self.stored.lock().await;
+
+        let secrets = stored
+            .applications
+            .get_mut(hostname)
+            .ok_or(SecretError::NotFound)?;
+        secrets.remove(name).ok_or(SecretError::NotFound)?;
+
+        if secrets.is_empty() {
+            stored.applications.remove(hostname);
+        }
+
+        self.write(&stored).await.map_err(SecretError::Io)
+    }
+
+    /// Delete every secret of an application.
+    pub async fn remove_application(&self, hostname: &str) -> io::Result<()> {
+        let mut stored = self.stored.lock().await;
+
+        if stored.applications.remove(hostname).is_some() {
+            info!("deleted the secrets of {hostname}");
+            self.write(&stored).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, stored: &Stored) -> io::Result<()> {
+        let content = toml::to_string_pretty(stored).expect("secrets should be serializable");
+
+        persistence::write_private(&self.path, content.as_bytes()).await
+    }
+}
+
+/// The cipher for the key passed as a systemd credential, or else the key in [`KEY_FILE`],
+/// which is generated if it does not exist yet.
+async fn cipher(root: &Path) -> io::Result<XChaCha20Poly1305> {
+    let credential = env::var_os(CREDENTIALS_DIRECTORY_VARIABLE)
+        .map(|directory| PathBuf::from(directory).join(KEY_CREDENTIAL));
+
+    let path = match credential {
+        Some(credential) if fs::try_exists(&credential).await? => credential,
+        _ => {
+            let path = root.join(KEY_FILE);
+
+            if !fs::try_exists(&path).await? {
+                let key = Key::<XChaCha20Poly1305>::generate();
+                let content = format!("{}\n", STANDARD.encode(key));
+
+                persistence::write_private(&path, content.as_bytes()).await?;
+                info!("generated a key for secrets in `{}`", path.display());
+            }
+
+            let mode = fs::metadata(&path).await?.permissions().mode();
+            if mode & 0o077 != 0 {
+                warn!(
+                    "`{}` is readable by other users, it should only be readable by root",
+                    path.display()
+                );
+            }
+
+            path
+        }
+    };
+
+    let content = fs::read_to_string(&path).await?;
+    let key = STANDARD
+        .decode(content.trim())
+        .map_err(|_| invalid_key(&path))?;
+
+    XChaCha20Poly1305::new_from_slice(&key).map_err(|_| invalid_key(&path))
+}
+
+fn invalid_key(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "`{}` should hold a base64 encoded 32 byte key",
+            path.display()
+        ),
+    )
+}
+
+/// Bind a ciphertext to the application and name it is stored under, so it cannot be moved.
+fn associated_data(hostname: &str, name: &str) -> Vec<u8> {
+    format!("{hostname}\0{name}").into_bytes()
+}
+
+fn check_name(name: &str) -> Result<(), SecretError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    match valid {
+        true => Ok(()),
+        false => Err(SecretError::InvalidName(name.to_owned())),
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    InvalidName(String),
+    NotFound,
+    Encryption,
+    Decryption,
+    Io(io::Error),
+}
+
+impl Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::InvalidName(name) => write!(
+                f,
+                "invalid secret name `{name}`, use at most {MAX_NAME_LENGTH} letters, digits, `-`, `_` and `.`"
+            ),
+            SecretError::NotFound => write!(f, "no such secret"),
+            SecretError::Encryption => write!(f, "failed to encrypt secret"),
+            SecretError::Decryption => write!(
+                f,
+                "failed to decrypt secret, it is corrupt or was encrypted with another key"
+            ),
+            SecretError::Io(e) => write!(f, "failed to save secrets: {e}"),
+        }
+    }
+}
+
+impl Error for SecretError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let directory = TempDir::new().unwrap();
+        let root = directory.path();
+        let store = SecretStore::open(root).await.unwrap();
+
+        store.set("a.example.com", "token", "value").await.unwrap();
+        assert_eq!(store.get("a.example.com", "token").await.unwrap(), "value");
+        assert_eq!(store.list("a.example.com").await, ["token"]);
+
+        let content = fs::read_to_string(root.join(SECRETS_FILE)).await.unwrap();
+        assert!(!content.contains("value"));
+
+        // The key is kept, so the secrets can be read after a restart.
+        let reopened = SecretStore::open(root).await.unwrap();
+        assert_eq!(
+            reopened.get("a.example.com", "token").await.unwrap(),
+            "value"
+        );
+    }
+
+    #[tokio::test]
+    async fn bound_to_application_and_name() {
+        let directory = TempDir::new().unwrap();
+        let root = directory.path();
+        let store = SecretStore::open(root).await.unwrap();
+
+        store.set("a.example.com", "token", "value").await.unwrap();
+
+        {
+            let mut stored = store.stored.lock().await;
+            let encrypted = stored.applications["a.example.com"]["token"].clone();
+
+            stored
+                .applications
+                .entry("b.example.com".into())
+                .or_default()
+                .insert("token".into(), encrypted.clone());
+            stored
+                .applications
+                .get_mut("a.example.com")
+                .unwrap()
+                .insert("other".into(), encrypted);
+        }
+
+        assert!(matches!(
+            store.get("b.example.com", "token").await,
+            Err(SecretError::Decryption)
+        ));
+        assert!(matches!(
+            store.get("a.example.com", "other").await,
+            Err(SecretError::Decryption)
+        ));
+        assert_eq!(store.get("a.example.com", "token").await.unwrap(), "value");
+    }
+
+    #[tokio::test]
+    async fn other_key() {
+        let directory = TempDir::new().unwrap();
+        let root = directory.path();
+        SecretStore::open(root)
+            .await
+            .unwrap()
+            .set("a.example.com", "token", "value")
+            .await
+            .unwrap();
+
+        fs::remove_file(root.join(KEY_FILE)).await.unwrap();
+        let store = SecretStore::open(root).await.unwrap();
+
+        assert!(matches!(
+            store.get("a.example.com", "token").await,
+            Err(SecretError::Decryption)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let directory = TempDir::new().unwrap();
+        let root = directory.path();
+        let store = SecretStore::open(root).await.unwrap();
+
+        store.set("a.example.com", "one", "1").await.unwrap();
+        store.set("a.example.com", "two", "2").await.unwrap();
+
+        store.delete("a.example.com", "one").await.unwrap();
+        assert!(matches!(
+            store.get("a.example.com", "one").await,
+            Err(SecretError::NotFound)
+        ));
+        assert!(matches!(
+            store.delete("a.example.com", "one").await,
+            Err(SecretError::NotFound)
+        ));
+
+        store.remove_application("a.example.com").await.unwrap();
+        assert!(store.list("a.example.com").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn export_and_import() {
+        let (from, to) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let exporting = SecretStore::open(from.path()).await.unwrap();
+        let importing = SecretStore::open(to.path()).await.unwrap();
+
+        exporting.set("a.example.com", "token", "a").await.unwrap();
+        exporting.set("b.example.com", "token", "b").await.unwrap();
+        importing
+            .set("a.example.com", "other", "kept")
+            .await
+            .unwrap();
+
+        let exported = exporting.export(&["a.example.com"]).await.unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported["a.example.com"]["token"], "a");
+
+        // Encrypted with the key of the importing store.
+        importing.import(&exported).await.unwrap();
+        assert_eq!(importing.get("a.example.com", "token").await.unwrap(), "a");
+        assert_eq!(
+            importing.get("a.example.com", "other").await.unwrap(),
+            "kept"
+        );
+
+        let mut invalid = Secrets::new();
+        invalid
+            .entry("a.example.com".into())
+            .or_default()
+            .insert("../x".into(), "value".into());
+        assert!(matches!(
+            importing.import(&invalid).await,
+            Err(SecretError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn names() {
+        assert!(check_name("api-token_2.v1").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("../x").is_err());
+        assert!(check_name("with space").is_err());
+        assert!(check_name(&"x".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use super::{persistence, secrets::SecretError, Configuration};
+use core::fmt::{self, Display};
+use sail_config::{
+    export::{Document, DocumentError, EmbeddedFile, Secrets},
+    validation, Change, Configurable, CurrentConfiguration,
+};
+use sail_core::{
+    diagnostic::Diagnostic,
+    export::{Format, ImportMode},
+    plan::Plan,
+};
+use std::{
+    error::Error,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::fs::{self, DirBuilder};
+use tracing::{info, warn};
+
+/// Directory inside the configuration directory that imported TLS files are written to.
+pub const TLS_DIRECTORY: &str = "tls";
+
+/// Suffix of an imported file while it is staged next to the file it replaces.
+const STAGED_SUFFIX: &str = ".import";
+
+impl Configuration {
+    /// The complete configuration as a single document, optionally with the TLS certificates
+    /// and keys it refers to and the secrets of the applications.
+    pub async fn export(&self, format: Format, secrets: bool) -> io::Result<String> {
+        let configuration = self.get();
+        let mut files = Vec::new();
+        let mut exported = Secrets::new();
+
+        if secrets {
+            for path in configuration.core.tls_files() {
+                let contents = fs::read_to_string(&path).await.map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("failed to read `{}`: {e}", path.display()),
+                    )
+                })?;
+
+                files.push(EmbeddedFile { path, contents });
+            }
+
+            let hostnames: Vec<&str> = configuration
+                .applications
+                .iter()
+                .map(|application| application.hostname.as_str())
+                .collect();
+
+            exported = self
+                .secrets
+                .export(&hostnames)
+                .await
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
+
+        Ok(Document::new(
+            &configuration,
+            (*self.references()).clone(),
+            files,
+            exported,
+        )
+        .write(format))
+    }
+
+    /// Import a configuration exported with [`Configuration::export`], returning what changed.
+    /// The caller holds the [lock](Configuration::lock).
+    pub async fn import(
+        &self,
+        document: &str,
+        format: Format,
+        mode: ImportMode,
+        author: String,
+    ) -> Result<Vec<String>, ImportError> {
+        let mut document = Document::parse(document, format).map_err(ImportError::Document)?;
+        let old = self.get();
+
+        let new = match mode {
+            ImportMode::Merge => {
+                document.references.set_core(Vec::new());
+
+                CurrentConfiguration {
+                    core: old.core.clone(),
+                    applications: Plan::compute(
+                        0,
+                        &old.applications,
+                        &document.applications,
+                        false,
+                    )
+                    .apply(&old.applications),
+                }
+            }
+            ImportMode::Replace => document.configuration(),
+        };
+
+        if let Some(hostname) = document
+            .secrets
+            .keys()
+            .find(|hostname| !new.applications.iter().any(|a| a.hostname == **hostname))
+        {
+            return Err(ImportError::UnexpectedSecrets(hostname.clone()));
+        }
+
+        let staged = match document.files.is_empty() {
+            true => Staged::default(),
+            false => {
+                // Files are only written where the imported listeners expect them.
+                if mode == ImportMode::Merge {
+                    return Err(ImportError::FilesWithMerge);
+                }
+
+                let expected = document.core.tls_files();
+                if let Some(file) = document.files.iter().find(|f| !expected.contains(&f.path)) {
+                    return Err(ImportError::UnexpectedFile(file.path.clone()));
+                }
+
+                // Never any other file, whatever the document names.
+                let directory = self.root.join(TLS_DIRECTORY);
+                if let Some(file) = document
+                    .files
+                    .iter()
+                    .find(|f| !is_importable(&f.path, &directory))
+                {
+                    return Err(ImportError::OutsideTlsDirectory(file.path.clone()));
+                }
+
+                Staged::write(&directory, &document.files)
+                    .await
+                    .map_err(ImportError::Io)?
+            }
+        };
+
+        let errors: Vec<Diagnostic> = validation::validate(&staged.apply(&new))
+            .into_iter()
+            .filter(|d| d.is_error())
+            .collect();
+        if !errors.is_empty() {
+            staged.discard().await;
+            return Err(ImportError::Invalid(errors));
+        }
+
+        // Secrets only take effect for the applications they belong to, so they can be stored
+        // before the configuration.
+        if let Err(e) = self.secrets.import(&document.secrets).await {
+            staged.discard().await;
+            return Err(ImportError::Secrets(e));
+        }
+
+        let mut changes = old.changes(&new);
+
+        if !changes.is_empty() {
+            let description = match mode {
+                ImportMode::Merge => "import configuration, merged",
+                ImportMode::Replace => "import configuration, replaced",
+            };
+
+            self.add_references(document.references);
+
+            // Like `set`, but the files replace the originals between storing the configuration
+            // and swapping it in, so listeners never read the imported files for the previous
+            // configuration or the original files for the imported one.
+            if let Err(e) = self
+                .backend
+                .commit(
+                    &old,
+                    &new,
+                    &self.references(),
+                    Change::new(author, description),
+                )
+                .await
+            {
+                staged.discard().await;
+                return Err(ImportError::Save(e));
+            }
+        }
+
+        let installed = staged.install().await;
+        self.replace(new);
+        installed.map_err(ImportError::Io)?;
+
+        changes.extend(
+            staged
+                .files
+                .iter()
+                .map(|(_, path)| format!("wrote `{}`", path.display())),
+        );
+        changes.extend(document.secrets.iter().map(|(hostname, secrets)| {
+            let names: Vec<&str> = secrets.keys().map(String::as_str).collect();
+            format!("imported secrets of {hostname}: {}", names.join(", "))
+        }));
+
+        Ok(changes)
+    }
+}
+
+/// Whether an imported file may be written to `path`: directly in `directory`, and not hidden
+/// like staged and temporary files.
+fn is_importable(path: &Path, directory: &Path) -> bool {
+    path.parent() == Some(directory)
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| !name.starts_with('.'))
+}
+
+/// Imported files, written next to the files they replace until the configuration is stored.
+#[derive(Default)]
+struct Staged {
+    /// Where each file is staged, and the file it replaces.
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Staged {
+    async fn write(directory: &Path, files: &[EmbeddedFile]) -> io::Result<Self> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(directory)
+            .await?;
+
+        let mut staged = Self::default();
+
+        for file in files {
+            // Listeners may share a certificate, which is then embedded more than once.
+            if staged.files.iter().any(|(_, path)| *path == file.path) {
+                continue;
+            }
+
+            let mut name = OsString::from(".");
+            name.extend(file.path.file_name());
+            name.push(STAGED_SUFFIX);
+            let path = directory.join(name);
+
+            if let Err(e) = persistence::write_private(&path, file.contents.as_bytes()).await {
+                staged.discard().await;
+                return Err(e);
+            }
+
+            staged.files.push((path, file.path.clone()));
+        }
+
+        Ok(staged)
+    }
+
+    /// The configuration with the staged files in place of the ones they replace.
+    fn apply(&self, configuration: &CurrentConfiguration) -> CurrentConfiguration {
+        let mut configuration = configuration.clone();
+
+        for tls in configuration
+            .core
+            .listeners
+            .iter_mut()
+            .filter_map(|listener| listener.tls.as_mut())
+        {
+            for path in [&mut tls.certificate, &mut tls.key] {
+                if let Some((staged, _)) = self.files.iter().find(|(_, file)| file == path) {
+                    *path = staged.clone();
+                }
+            }
+        }
+
+        configuration
+    }
+
+    /// Replace the original files.
+    async fn install(&self) -> io::Result<()> {
+        for (staged, path) in self.files.iter() {
+            info!("writing `{}`", path.display());
+
+            fs::rename(staged, path).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn discard(&self) {
+        for (staged, _) in self.files.iter() {
+            if let Err(e) = fs::remove_file(staged).await {
+                warn!("failed to remove `{}`: {e}", staged.display());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Document(DocumentError),
+    FilesWithMerge,
+    UnexpectedFile(PathBuf),
+    OutsideTlsDirectory(PathBuf),
+    UnexpectedSecrets(String),
+    Secrets(SecretError),
+    Io(io::Error),
+    Save(io::Error),
+    Invalid(Vec<Diagnostic>),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Document(e) => write!(f, "invalid document: {e}"),
+            ImportError::FilesWithMerge => write!(
+                f,
+                "files can only be imported when replacing the configuration, the core configuration is kept when merging"
+            ),
+            ImportError::UnexpectedFile(path) => write!(
+                f,
+                "`{}` is not a TLS certificate or key of an imported listener",
+                path.display()
+            ),
+            ImportError::OutsideTlsDirectory(path) => write!(
+                f,
+                "`{}` is not in the `{TLS_DIRECTORY}` directory of the configuration directory, where TLS files are imported to",
+                path.display()
+            ),
+            ImportError::UnexpectedSecrets(hostname) => write!(
+                f,
+                "the document has secrets of {hostname}, which is not an imported application"
+            ),
+            ImportError::Secrets(e) => write!(f, "failed to import secrets: {e}"),
+            ImportError::Io(e) => write!(f, "failed to write file: {e}"),
+            ImportError::Save(e) => write!(f, "failed to save configuration: {e}"),
+            ImportError::Invalid(diagnostics) => {
+                write!(f, "the imported configuration is invalid")?;
+
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {diagnostic}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ImportError {}
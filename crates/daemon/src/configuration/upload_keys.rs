@@ -0,0 +1,147 @@
+use super::persistence;
+use sail_core::upload_key::{self, UploadKeys};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, sync::Mutex};
+use tracing::info;
+
+/// File inside the configuration directory with the hashes of the upload keys.
+pub const UPLOAD_KEYS_FILE: &str = "upload-keys.toml";
+
+#[derive(Default, Deserialize, Serialize)]
+struct Stored {
+    /// By hostname of the application.
+    #[serde(default)]
+    applications: BTreeMap<String, UploadKeys>,
+}
+
+/// The upload keys of applications, of which only the hashes are stored.
+pub struct UploadKeyStore {
+    path: PathBuf,
+    stored: Mutex<Stored>,
+}
+
+impl UploadKeyStore {
+    pub async fn open(root: &Path) -> io::Result<Self> {
+        let path = root.join(UPLOAD_KEYS_FILE);
+
+        let stored = match fs::read_to_string(&path).await {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            stored: Mutex::new(stored),
+        })
+    }
+
+    /// Generate a new upload key for an application, returning the key. The current key keeps
+    /// working for `grace_period`, or stops working right away without one.
+    pub async fn rotate(&self, hostname: &str, grace_period: Duration) -> io::Result<String> {
+        let key = upload_key::generate();
+        let now = now();
+
+        // Stored as a TOML integer, which is signed.
+        let expires = match grace_period.is_zero() {
+            true => None,
+            false => Some(
+                now.checked_add(grace_period.as_secs())
+                    .filter(|expires| i64::try_from(*expires).is_ok())
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "grace period is too long")
+                    })?,
+            ),
+        };
+
+        let mut stored = self.stored.lock().await;
+
+        match stored.applications.get_mut(hostname) {
+            Some(keys) => {
+                keys.rotate(&key, expires);
+                keys.expire(now);
+            }
+            None => {
+                stored
+                    .applications
+                    .insert(hostname.to_owned(), UploadKeys::new(&key));
+            }
+        }
+
+        self.write(&stored).await?;
+
+        info!("generated a new upload key for {hostname}");
+
+        Ok(key)
+    }
+
+    /// Generate upload keys for the applications that have none, returning the new keys by
+    /// hostname.
+    pub async fn generate_missing<'a>(
+        &self,
+        hostnames: impl IntoIterator<Item = &'a str>,
+    ) -> io::Result<BTreeMap<String, String>> {
+        let mut stored = self.stored.lock().await;
+        let mut keys = BTreeMap::new();
+
+        for hostname in hostnames {
+            if !stored.applications.contains_key(hostname) {
+                let key = upload_key::generate();
+
+                stored
+                    .applications
+                    .insert(hostname.to_owned(), UploadKeys::new(&key));
+                keys.insert(hostname.to_owned(), key);
+            }
+        }
+
+        if !keys.is_empty() {
+            self.write(&stored).await?;
+
+            info!("generated upload keys for {} applications", keys.len());
+        }
+
+        Ok(keys)
+    }
+
+    /// Whether `key` is a working upload key of an application.
+    pub async fn verify(&self, hostname: &str, key: &str) -> bool {
+        self.stored
+            .lock()
+            .await
+            .applications
+            .get(hostname)
+            .is_some_and(|keys| keys.verify(key, now()))
+    }
+
+    /// Delete the upload keys of an application.
+    pub async fn remove_application(&self, hostname: &str) -> io::Result<()> {
+        let mut stored = self.stored.lock().await;
+
+        if stored.applications.remove(hostname).is_some() {
+            self.write(&stored).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, stored: &Stored) -> io::Result<()> {
+        let content = toml::to_string_pretty(stored).expect("upload keys should be serializable");
+
+        persistence::write_private(&self.path, content.as_bytes()).await
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -1,48 +1,64 @@
-use crate::configuration::Configuration;
-use sail_config::{Configurable, CurrentConfiguration};
-use sail_core::control::{Message, Reply, Request, Response};
-use std::os::fd::FromRawFd;
-use std::{env, sync::Arc};
+use crate::{audit::AuditLog, configuration::Configuration, metrics::Metrics, systemd::ListenFds};
+use nix::unistd::{getgrouplist, Gid, Group, Uid, User};
+use sail_config::{validation, Change, Configurable, CurrentConfiguration};
+use sail_core::{
+    access::Role,
+    audit::Caller,
+    control::{Message, Reply, Request, Response},
+    plan::Plan,
+    protocol::Hello,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    io,
+    os::fd::{AsFd, BorrowedFd},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::watch;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::UnixListener,
-    pin,
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines},
+    net::{unix::UCred, UnixListener},
+    pin, task,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// `FileDescriptorName=` of the control socket unit.
+pub const CONTROL_SOCKET_NAME: &str = "control";
+const LEGACY_CONTROL_SOCKET_NAME: &str = "sail.socket";
 
 pub struct Interface {
     socket: UnixListener,
     config: Arc<Configuration>,
+    metrics: Arc<Metrics>,
+    audit: Arc<AuditLog>,
 }
 
 impl Interface {
-    pub fn attach_to_systemd_socket(config: Arc<Configuration>) -> Self {
+    pub fn attach_to_systemd_socket(
+        config: Arc<Configuration>,
+        metrics: Arc<Metrics>,
+        audit: Arc<AuditLog>,
+        sockets: &mut ListenFds,
+    ) -> Self {
         {
             use std::os::unix::net::UnixListener as StdUnixListener;
 
-            let fd: i32 = match env::var("LISTEN_FDS") {
-                Ok(fd) => fd.parse().expect("file descriptor should be valid integer"),
-                Err(e) => {
-                    error!("Missing LISTEN_FDS: {e}");
-                    for var in env::vars() {
-                        error!("var: {var:?}")
-                    }
+            // Installations from before the socket was named pass it under the unit name.
+            let mut fds = sockets.take(CONTROL_SOCKET_NAME);
+            fds.extend(sockets.take(LEGACY_CONTROL_SOCKET_NAME));
 
-                    panic!("LISTEN_FDS invariant broken")
-                }
-            };
-
-            info!("LISTEN_FDS={}", fd);
-
-            if fd != 1 {
-                panic!("Received unexpected file descriptor `{fd}` from systemd!")
+            if fds.len() != 1 {
+                panic!(
+                    "Expected exactly one `{CONTROL_SOCKET_NAME}` socket from systemd, received {}",
+                    fds.len()
+                )
             }
 
-            // SAFETY: this comes from systemd
-            let std_listener = unsafe { StdUnixListener::from_raw_fd(3) };
+            let std_listener = StdUnixListener::from(fds.remove(0));
             std_listener
                 .set_nonblocking(true)
                 .expect("should be able to set non-blocking on the socket");
@@ -53,10 +69,16 @@ impl Interface {
                 socket: UnixListener::from_std(std_listener)
                     .expect("converting std::net::UnixListener to tokio::net::UnixListener"),
                 config,
+                metrics,
+                audit,
             }
         }
     }
 
+    pub fn socket(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+
     pub async fn handle_requests(&self) {
         let (stop_tx, mut stop_rx) = watch::channel(());
 
@@ -79,26 +101,85 @@ impl Interface {
                     info!("new socket connection");
 
                     let cfg = self.config.clone();
+                    let metrics = self.metrics.clone();
+                    let audit = self.audit.clone();
 
                     tokio::spawn(async move {
+                        // Looking up users and groups can block on NSS, like LDAP.
+                        let (caller, groups) = match stream.peer_cred() {
+                            Ok(credentials) => task::spawn_blocking(move || {
+                                let caller = caller(credentials);
+                                let groups = groups(&caller);
+
+                                (Some(caller), groups)
+                            })
+                            .await
+                            .expect("looking up the caller should succeed"),
+                            Err(e) => {
+                                error!("failed to get credentials of control connection: {e}");
+                                (None, Vec::new())
+                            }
+                        };
+                        let author = caller
+                            .as_ref()
+                            .map_or_else(|| "unknown".into(), Caller::to_string);
+
                         let (reader, writer) = stream.split();
                         pin!(writer);
 
                     let mut lines = BufReader::new(reader).lines();
-                    while let Some(message) = lines
+
+                    if !handshake(&mut lines, &mut writer).await {
+                        return;
+                    }
+
+                    while let Some(line) = lines
                         .next_line()
                         .await
                         .expect("reading from the stream should succeed")
-                        .map(|l| {
-                            serde_json::from_str::<Message>(&l)
-                                .expect("deserialization of message should succeed")
-                        })
                     {
+                        let message = match serde_json::from_str::<Message>(&line) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                error!("closing control connection, invalid message: {e}");
+                                break;
+                            }
+                        };
+
+                        let changing = match changes_configuration(&message.request) {
+                            true => Some(cfg.lock().await),
+                            false => None,
+                        };
                         let config = cfg.get();
 
+                        metrics.record_control_request(&message.request);
+
+                        let audited = message
+                            .request
+                            .is_audited()
+                            .then(|| message.request.clone());
+
+                        // Without access configuration, everyone who can connect is an admin.
+                        let role = match &config.core.access {
+                            None => Some(Role::Admin),
+                            Some(access) => caller
+                                .as_ref()
+                                .and_then(|caller| access.role(caller.uid, &groups)),
+                        };
+
                         let reply = Reply {
                             regarding: message.id,
                             response: match message.request {
+                                request if role < Some(request.required_role()) => {
+                                    warn!("denied {} request of {author}", request.name());
+
+                                    denied(
+                                        &format!("`{}`", request.name()),
+                                        request.required_role(),
+                                        &author,
+                                        role,
+                                    )
+                                }
                                 Request::CreateApplication { application } => {
                                     let mut applications = config.applications.clone();
 
@@ -113,19 +194,50 @@ impl Interface {
                                     } else {
                                         applications.push(application.clone());
 
-                                        cfg
-                                            .set(CurrentConfiguration {
-                                                core: config.core.clone(),
-                                                applications,
-                                            })
-                                            .await;
+                                        let new = CurrentConfiguration {
+                                            core: config.core.clone(),
+                                            applications,
+                                        };
+                                        let errors = errors(&new);
 
-                                        info!(
-                                            "created application {} -> {}",
-                                            application.hostname, application.address
-                                        );
+                                        if !errors.is_empty() {
+                                            Response::Error {
+                                                message: format!(
+                                                    "the application would result in an invalid configuration:\n{}",
+                                                    errors.join("\n")
+                                                ),
+                                            }
+                                        } else {
+                                            match cfg
+                                                .set(
+                                                    new,
+                                                    Change::new(
+                                                        author.clone(),
+                                                        format!("create application {}", application.hostname),
+                                                    ),
+                                                )
+                                                .await
+                                            {
+                                                Ok(()) => {
+                                                    info!(
+                                                        "created application {} -> {}",
+                                                        application.hostname, application.address
+                                                    );
 
-                                        Response::Success
+                                                    match cfg
+                                                        .upload_keys()
+                                                        .rotate(&application.hostname, Duration::ZERO)
+                                                        .await
+                                                    {
+                                                        Ok(key) => Response::UploadKey { key },
+                                                        Err(e) => Response::Error {
+                                                            message: format!("created application, but failed to create its upload key, rotate it to try again: {e}"),
+                                                        },
+                                                    }
+                                                }
+                                                Err(e) => save_failed(e),
+                                            }
+                                        }
                                     }
                                 }
                                 Request::DeleteApplication { hostname } => {
@@ -141,16 +253,28 @@ impl Interface {
                                             .filter(|a| a.hostname != hostname)
                                             .collect();
 
-                                        cfg
-                                            .set(CurrentConfiguration {
-                                                core: config.core.clone(),
-                                                applications: new_applications,
-                                            })
-                                            .await;
+                                        match cfg
+                                            .set(
+                                                CurrentConfiguration {
+                                                    core: config.core.clone(),
+                                                    applications: new_applications,
+                                                },
+                                                Change::new(
+                                                    author.clone(),
+                                                    format!("delete application {hostname}"),
+                                                ),
+                                            )
+                                            .await
+                                        {
+                                            Ok(()) => {
+                                                cfg.forget(&hostname).await;
 
-                                        info!("deleted application {hostname}");
+                                                info!("deleted application {hostname}");
 
-                                        Response::Success
+                                                Response::Success
+                                            }
+                                            Err(e) => save_failed(e),
+                                        }
                                     }
                                 }
                                 Request::GetApplications => {
@@ -164,14 +288,277 @@ impl Interface {
                                     Response::Status {
                                         port: cfg.get().core.port,
                                         applications: cfg.get().applications.clone(),
+                                        problems: cfg.problems().to_vec(),
+                                    }
+                                }
+                                Request::ValidateConfiguration => {
+                                    info!("validating stored configuration");
+
+                                    Response::Diagnostics {
+                                        diagnostics: cfg.backend().load().await.diagnostics,
                                     }
                                 }
-                                Request::ValidateConfiguration => Response::Error {
-                                    message: "todo!".into(),
+                                Request::GetHistory => match cfg.backend().entries().await {
+                                    Ok(entries) => Response::History { entries },
+                                    Err(e) => Response::Error {
+                                        message: format!("failed to read history: {e}"),
+                                    },
+                                },
+                                Request::DiffConfiguration { from, to } => {
+                                    let history = cfg.backend();
+
+                                    match (history.snapshot(from).await, history.snapshot(to).await) {
+                                        (Ok(Some(from)), Ok(Some(to))) => Response::Changes {
+                                            changes: from.configuration.changes(&to.configuration),
+                                        },
+                                        (Ok(None), _) => Response::Error {
+                                            message: format!("no configuration {from} in history"),
+                                        },
+                                        (_, Ok(None)) => Response::Error {
+                                            message: format!("no configuration {to} in history"),
+                                        },
+                                        (Err(e), _) | (_, Err(e)) => Response::Error {
+                                            message: format!("failed to read history: {e}"),
+                                        },
+                                    }
+                                }
+                                Request::Rollback { number } => {
+                                    match cfg.backend().snapshot(number).await {
+                                        // Operators manage applications, only admins may change
+                                        // the core configuration, including `access`.
+                                        Ok(Some(snapshot))
+                                            if snapshot.configuration.core != config.core
+                                                && role < Some(Role::Admin) =>
+                                        {
+                                            warn!("denied rollback of the core configuration of {author}");
+
+                                            denied(
+                                                &format!("rolling back to configuration {number}, which has a different core configuration,"),
+                                                Role::Admin,
+                                                &author,
+                                                role,
+                                            )
+                                        }
+                                        Ok(Some(snapshot)) => {
+                                            let errors = errors(&snapshot.configuration);
+
+                                            if !errors.is_empty() {
+                                                Response::Error {
+                                                    message: format!(
+                                                        "configuration {number} is not valid anymore:\n{}",
+                                                        errors.join("\n")
+                                                    ),
+                                                }
+                                            } else {
+                                                cfg.add_references(snapshot.references);
+
+                                                match cfg
+                                                    .set(
+                                                        snapshot.configuration,
+                                                        Change::new(
+                                                            author.clone(),
+                                                            format!("rollback to configuration {number}"),
+                                                        ),
+                                                    )
+                                                    .await
+                                                {
+                                                    Ok(()) => {
+                                                        info!("rolled back to configuration {number}");
+
+                                                        Response::Success
+                                                    }
+                                                    Err(e) => save_failed(e),
+                                                }
+                                            }
+                                        }
+                                        Ok(None) => Response::Error {
+                                            message: format!("no configuration {number} in history"),
+                                        },
+                                        Err(e) => Response::Error {
+                                            message: format!("failed to read history: {e}"),
+                                        },
+                                    }
+                                }
+                                Request::PlanApplications { applications, prune } => {
+                                    match cfg.backend().number().await {
+                                        Ok(base) => {
+                                            let plan = Plan::compute(
+                                                base,
+                                                &config.applications,
+                                                &applications,
+                                                prune,
+                                            );
+
+                                            let diagnostics = validation::validate(&CurrentConfiguration {
+                                                core: config.core.clone(),
+                                                applications: plan.apply(&config.applications),
+                                            });
+
+                                            Response::Plan { plan, diagnostics }
+                                        }
+                                        Err(e) => Response::Error {
+                                            message: format!("failed to read history: {e}"),
+                                        },
+                                    }
+                                }
+                                Request::ApplyPlan { plan } => {
+                                    let applications = plan.apply(&config.applications);
+                                    let errors = errors(&CurrentConfiguration {
+                                        core: config.core.clone(),
+                                        applications: applications.clone(),
+                                    });
+
+                                    let base = cfg.backend().number().await;
+
+                                    if let Err(e) = base {
+                                        Response::Error {
+                                            message: format!("failed to read history: {e}"),
+                                        }
+                                    } else if base.ok() != Some(plan.base) {
+                                        Response::Error {
+                                            message: "the configuration changed since the plan was made, plan again".into(),
+                                        }
+                                    } else if !errors.is_empty() {
+                                        Response::Error {
+                                            message: format!(
+                                                "the plan would result in an invalid configuration:\n{}",
+                                                errors.join("\n")
+                                            ),
+                                        }
+                                    } else if plan.is_empty() {
+                                        Response::Applied {
+                                            changes: Vec::new(),
+                                            keys: BTreeMap::new(),
+                                        }
+                                    } else {
+                                        match cfg
+                                            .set(
+                                                CurrentConfiguration {
+                                                    core: config.core.clone(),
+                                                    applications,
+                                                },
+                                                Change::new(author.clone(), format!("apply plan: {plan}")),
+                                            )
+                                            .await
+                                        {
+                                            Ok(()) => {
+                                                info!("applied plan: {plan}");
+
+                                                let changes = plan.actions.iter().map(ToString::to_string).collect();
+                                                applied(&cfg, &config, changes).await
+                                            }
+                                            Err(e) => save_failed(e),
+                                        }
+                                    }
+                                }
+                                Request::ExportConfiguration { format, secrets } => {
+                                    match cfg.export(format, secrets).await {
+                                        Ok(document) => Response::Document { document },
+                                        Err(e) => Response::Error {
+                                            message: format!("failed to export configuration: {e}"),
+                                        },
+                                    }
+                                }
+                                Request::ImportConfiguration { document, format, mode } => {
+                                    match cfg.import(&document, format, mode, author.clone()).await {
+                                        Ok(changes) => applied(&cfg, &config, changes).await,
+                                        Err(e) => Response::Error {
+                                            message: e.to_string(),
+                                        },
+                                    }
+                                }
+                                Request::SetSecret { hostname, name, value } => {
+                                    if !config.applications.iter().any(|a| a.hostname == hostname) {
+                                        Response::Error {
+                                            message: format!("no app with hostname `{hostname}` exists"),
+                                        }
+                                    } else {
+                                        match cfg.secrets().set(&hostname, &name, &value).await {
+                                            Ok(()) => {
+                                                info!("{author} set secret {name} of {hostname}");
+                                                Response::Success
+                                            }
+                                            Err(e) => Response::Error {
+                                                message: e.to_string(),
+                                            },
+                                        }
+                                    }
+                                }
+                                Request::GetSecret { hostname, name } => {
+                                    match cfg.secrets().get(&hostname, &name).await {
+                                        Ok(value) => {
+                                            info!("{author} read secret {name} of {hostname}");
+                                            Response::Secret { value }
+                                        }
+                                        Err(e) => Response::Error {
+                                            message: e.to_string(),
+                                        },
+                                    }
+                                }
+                                Request::RotateUploadKey { hostname, grace_period } => {
+                                    if !config.applications.iter().any(|a| a.hostname == hostname) {
+                                        Response::Error {
+                                            message: format!("no app with hostname `{hostname}` exists"),
+                                        }
+                                    } else {
+                                        match cfg
+                                            .upload_keys()
+                                            .rotate(&hostname, Duration::from_secs(grace_period))
+                                            .await
+                                        {
+                                            Ok(key) => {
+                                                info!("{author} rotated the upload key of {hostname}");
+                                                Response::UploadKey { key }
+                                            }
+                                            Err(e) => Response::Error {
+                                                message: format!("failed to rotate upload key: {e}"),
+                                            },
+                                        }
+                                    }
+                                }
+                                Request::ListSecrets { hostname } => Response::Secrets {
+                                    names: cfg.secrets().list(&hostname).await,
+                                },
+                                Request::DeleteSecret { hostname, name } => {
+                                    match cfg.secrets().delete(&hostname, &name).await {
+                                        Ok(()) => {
+                                            info!("{author} deleted secret {name} of {hostname}");
+                                            Response::Success
+                                        }
+                                        Err(e) => Response::Error {
+                                            message: e.to_string(),
+                                        },
+                                    }
+                                }
+                                Request::VerifyUploadKey { hostname, key } => {
+                                    match cfg.upload_keys().verify(&hostname, &key).await {
+                                        true => Response::Success,
+                                        false => {
+                                            warn!("{author} gave an invalid upload key for {hostname}");
+
+                                            Response::Error {
+                                                message: format!("the upload key is not valid for `{hostname}`"),
+                                            }
+                                        }
+                                    }
+                                }
+                                Request::GetAuditLog { filter } => match audit.entries(&filter).await {
+                                    Ok(entries) => Response::AuditLog { entries },
+                                    Err(e) => Response::Error {
+                                        message: format!("failed to read audit log: {e}"),
+                                    },
                                 },
                             },
                         };
 
+                        drop(changing);
+
+                        if let Some(request) = audited {
+                            audit
+                                .record(caller.clone(), &request, (&reply.response).into())
+                                .await;
+                        }
+
                         writer
                             .write_all(
                                 format!(
@@ -189,3 +576,197 @@ impl Interface {
         }
     }
 }
+
+/// Whether a request changes the configuration, or depends on an application existing while it
+/// is handled.
+fn changes_configuration(request: &Request) -> bool {
+    match request {
+        Request::CreateApplication { .. }
+        | Request::DeleteApplication { .. }
+        | Request::Rollback { .. }
+        | Request::ApplyPlan { .. }
+        | Request::ImportConfiguration { .. }
+        | Request::SetSecret { .. }
+        | Request::RotateUploadKey { .. } => true,
+        Request::GetApplications
+        | Request::Status
+        | Request::ValidateConfiguration
+        | Request::GetHistory
+        | Request::DiffConfiguration { .. }
+        | Request::PlanApplications { .. }
+        | Request::ExportConfiguration { .. }
+        | Request::GetSecret { .. }
+        | Request::ListSecrets { .. }
+        | Request::DeleteSecret { .. }
+        | Request::VerifyUploadKey { .. }
+        | Request::GetAuditLog { .. } => false,
+    }
+}
+
+/// The errors that keep a configuration from being set.
+fn errors(configuration: &CurrentConfiguration) -> Vec<String> {
+    validation::validate(configuration)
+        .into_iter()
+        .filter(|d| d.is_error())
+        .map(|d| d.to_string())
+        .collect()
+}
+
+/// The reply to a request that needs a `required` role the caller does not have.
+fn denied(what: &str, required: Role, author: &str, role: Option<Role>) -> Response {
+    Response::Error {
+        message: format!(
+            "permission denied: {what} requires the {required} role, {author} has {}",
+            role.map_or_else(|| "no role".into(), |role| format!("the {role} role")),
+        ),
+    }
+}
+
+/// The reply to a change from `old` that was made, with upload keys for the applications it
+/// created. Applications that were removed some other way than deleting them still have theirs.
+async fn applied(
+    cfg: &Configuration,
+    old: &CurrentConfiguration,
+    changes: Vec<String>,
+) -> Response {
+    let new = cfg.get();
+    let created = new
+        .applications
+        .iter()
+        .filter(|a| !old.applications.iter().any(|o| o.hostname == a.hostname))
+        .map(|a| a.hostname.as_str());
+
+    match cfg.upload_keys().generate_missing(created).await {
+        Ok(keys) => Response::Applied { changes, keys },
+        Err(e) => Response::Error {
+            message: format!(
+                "changed the configuration, but failed to create the upload keys of new applications, rotate them to try again: {e}"
+            ),
+        },
+    }
+}
+
+/// The reply to a change that could not be stored, and was not made.
+fn save_failed(e: io::Error) -> Response {
+    error!("failed to save configuration: {e}");
+
+    Response::Error {
+        message: format!("failed to save configuration, nothing was changed: {e}"),
+    }
+}
+
+/// Exchange a [`Hello`] with `sail`, returning whether the connection can be used.
+async fn handshake<R, W>(lines: &mut Lines<BufReader<R>>, writer: &mut W) -> bool
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let line = match lines.next_line().await {
+        Ok(Some(line)) => line,
+        Ok(None) => return false,
+        Err(e) => {
+            error!("failed to read from control connection: {e}");
+            return false;
+        }
+    };
+
+    let ours = Hello::new("saild", env!("CARGO_PKG_VERSION"));
+
+    let theirs = match serde_json::from_str::<Hello>(&line) {
+        Ok(theirs) => theirs,
+        Err(e) => {
+            // Versions of `sail` from before the handshake start with a request, answer it with
+            // an error they can show.
+            match serde_json::from_str::<Message>(&line) {
+                Ok(message) => {
+                    warn!("closing control connection of a `sail` without handshake");
+
+                    let reply = Reply {
+                        regarding: message.id,
+                        response: Response::Error {
+                            message: format!(
+                                "this version of sail is too old for saild {}, upgrade sail",
+                                ours.version
+                            ),
+                        },
+                    };
+                    send(writer, &reply).await;
+                }
+                Err(_) => error!("closing control connection, expected a hello: {e}"),
+            }
+
+            return false;
+        }
+    };
+
+    send(writer, &ours).await;
+
+    match ours.negotiate(&theirs) {
+        Ok(version) => {
+            info!(
+                "{} {} connected with control protocol version {version}",
+                theirs.program, theirs.version
+            );
+            true
+        }
+        Err(e) => {
+            warn!("closing control connection: {e}");
+            false
+        }
+    }
+}
+
+async fn send<W>(writer: &mut W, value: &impl serde::Serialize)
+where
+    W: AsyncWrite + Unpin,
+{
+    let line = format!(
+        "{}\n",
+        serde_json::to_string(value).expect("serialization of control message should succeed")
+    );
+
+    if let Err(e) = writer.write_all(line.as_bytes()).await {
+        error!("failed to write to control connection: {e}")
+    }
+}
+
+/// The process on the other end of a control connection. Blocks on looking up the user.
+fn caller(credentials: UCred) -> Caller {
+    let uid = credentials.uid();
+
+    Caller {
+        uid,
+        gid: credentials.gid(),
+        pid: credentials.pid(),
+        user: match User::from_uid(Uid::from_raw(uid)) {
+            Ok(Some(user)) => Some(user.name),
+            _ => None,
+        },
+    }
+}
+
+/// Names of the groups of a caller: the group of the connection and the supplementary groups of
+/// their user. Blocks on looking up the groups.
+fn groups(caller: &Caller) -> Vec<String> {
+    let gid = Gid::from_raw(caller.gid);
+    let mut gids = vec![gid];
+
+    if let Some(name) = caller
+        .user
+        .as_deref()
+        .and_then(|user| CString::new(user).ok())
+    {
+        match getgrouplist(&name, gid) {
+            Ok(supplementary) => gids.extend(supplementary),
+            Err(e) => error!("failed to get the groups of {caller}: {e}"),
+        }
+    }
+
+    gids.sort_unstable_by_key(|gid| gid.as_raw());
+    gids.dedup();
+
+    gids.into_iter()
+        .filter_map(|gid| Group::from_gid(gid).ok().flatten())
+        .map(|group| group.name)
+        .collect()
+}
@@ -1,43 +1,140 @@
+mod audit;
 mod configuration;
 mod interface;
+mod metrics;
+mod options;
 mod server;
+mod systemd;
+mod telemetry;
+mod upgrade;
 
+use audit::AuditLog;
 use configuration::Configuration;
 use interface::Interface;
+use metrics::Metrics;
+use options::{Options, OptionsError};
+use sail_config::Configurable;
 use server::Server;
-use std::sync::Arc;
-use tokio::task::JoinSet;
-use tracing::{info, Level};
+use std::{os::fd::AsFd, process, sync::Arc};
+use systemd::{ListenEnv, ListenFds};
+use telemetry::Telemetry;
+use tokio::{runtime::Runtime, task::JoinSet};
+use tracing::{error, info};
+use upgrade::{Handoff, Predecessor};
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(Level::TRACE)
-        .init();
+fn main() {
+    let options = match Options::from_env() {
+        Ok(options) => options,
+        Err(OptionsError::Help) => {
+            println!("{}", OptionsError::Help);
+            process::exit(0)
+        }
+        Err(e) => {
+            eprintln!("ERROR: {e}");
+            process::exit(2)
+        }
+    };
+
+    // Before the runtime starts its threads, which may read the environment meanwhile.
+    let listen_env = ListenEnv::take();
+    let predecessor = Predecessor::connect();
+
+    Runtime::new()
+        .expect("should be able to start the runtime")
+        .block_on(run(options, listen_env, predecessor))
+}
+
+async fn run(options: Options, listen_env: ListenEnv, mut predecessor: Option<Predecessor>) {
+    let telemetry = Telemetry::init();
 
     info!("starting");
 
     let mut tasks = JoinSet::new();
 
-    let configuration: Arc<Configuration> = Arc::new(Configuration::from_filesystem().await);
+    let configuration: Arc<Configuration> =
+        Arc::new(Configuration::open(options.config_dir.clone(), options.backend).await);
 
-    let config = configuration.clone();
-    tasks.spawn(async move {
-        // The interface attaches to the systemd socket to listen for and process request messages sent by the CLI tool `sail`.
-        Interface::attach_to_systemd_socket(config)
-            .handle_requests()
+    // After the configuration, which creates the configuration directory.
+    let audit = Arc::new(
+        AuditLog::open(&options.config_dir)
             .await
-    });
+            .expect("should be able to open the audit log"),
+    );
 
-    tasks.spawn(async move {
-        let server = Server::with_config(configuration);
+    let metrics = Arc::new(Metrics::default());
+
+    // After an upgrade, the sockets come from the previous daemon instead of systemd.
+    let mut sockets = match predecessor.as_ref().map(Predecessor::inherit) {
+        Some(Ok(sockets)) => sockets,
+        Some(Err(e)) => {
+            // When the previous daemon is still running, it sees the connection close, stops
+            // this daemon and keeps serving.
+            error!("failed to inherit sockets from previous daemon, using systemd's: {e}");
+            predecessor = None;
+
+            ListenFds::from_env(listen_env)
+        }
+        None => ListenFds::from_env(listen_env),
+    };
+
+    let handoff = Handoff::default();
+
+    if let Some(port) = configuration.get().core.metrics_port {
+        match metrics::bind(port, &mut sockets) {
+            Ok(listener) => {
+                handoff.register(metrics::SOCKET_NAME, listener.as_fd());
+
+                let metrics = metrics.clone();
+                tasks.spawn(async move { metrics::serve(metrics, listener).await });
+            }
+            Err(e) => error!("binding metrics listener to port {port} failed: {e}"),
+        }
+    }
+
+    // The interface attaches to the systemd socket to listen for and process request messages sent by the CLI tool `sail`.
+    let interface = Interface::attach_to_systemd_socket(
+        configuration.clone(),
+        metrics.clone(),
+        audit,
+        &mut sockets,
+    );
+
+    // Any sockets besides the control socket are HTTP listeners.
+    let server = Server::new(
+        configuration.clone(),
+        metrics.clone(),
+        sockets,
+        handoff.clone(),
+    );
+
+    handoff.register(interface::CONTROL_SOCKET_NAME, interface.socket());
+
+    tasks.spawn(configuration::watch(configuration.clone()));
+
+    tasks.spawn(metrics::watch(metrics.clone(), configuration.subscribe()));
 
+    tasks.spawn(async move { interface.handle_requests().await });
+
+    tasks.spawn(async move {
         server.start().await;
 
         info!("Finished serving")
     });
 
+    tasks.spawn(handoff.listen());
+
+    match predecessor {
+        Some(predecessor) => {
+            // Take over as the main process of the service before the previous daemon exits.
+            systemd::notify(&format!("MAINPID={}\nREADY=1", process::id()));
+            predecessor.ready();
+        }
+        None => systemd::notify("READY=1"),
+    }
+
     while tasks.join_next().await.is_some() {
         info!("Finished task, {} left", tasks.len());
     }
+
+    telemetry.shutdown();
 }
@@ -0,0 +1,327 @@
+use crate::systemd::ListenFds;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use hyper::StatusCode;
+use sail_config::events::{Event, Update};
+use sail_core::{control::Request, proxy::FetchError};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io,
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::{
+    net::TcpListener,
+    select,
+    signal::unix::{signal, SignalKind},
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{error, info};
+
+/// Upper bounds (in seconds) of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Name of the metrics socket when it is handed over during an upgrade.
+pub const SOCKET_NAME: &str = "metrics";
+
+/// Label used for requests that are handled by the web interface.
+pub const WEB_APPLICATION: &str = "web";
+
+/// Label used for requests whose host does not match any application.
+pub const UNKNOWN_APPLICATION: &str = "unknown";
+
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    applications: BTreeMap<String, ApplicationMetrics>,
+    upstream_errors: BTreeMap<&'static str, u64>,
+    control_requests: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Default)]
+struct ApplicationMetrics {
+    requests: u64,
+    in_flight: i64,
+    statuses: BTreeMap<&'static str, u64>,
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+impl Metrics {
+    /// Start tracking a request to `application`, counting it as in-flight until the returned
+    /// tracker is dropped.
+    pub fn track(self: &Arc<Self>, application: impl Into<String>) -> Tracker {
+        let application = application.into();
+
+        self.with_application(&application, |metrics| {
+            metrics.requests += 1;
+            metrics.in_flight += 1;
+        });
+
+        Tracker {
+            metrics: self.clone(),
+            application,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record_upstream_error(&self, error: &FetchError) {
+        *self
+            .registry()
+            .upstream_errors
+            .entry(error.name())
+            .or_default() += 1;
+    }
+
+    pub fn record_control_request(&self, request: &Request) {
+        *self
+            .registry()
+            .control_requests
+            .entry(request.name())
+            .or_default() += 1;
+    }
+
+    /// Stop reporting an application that was removed, unless requests to it are still in flight.
+    pub fn remove_application(&self, application: &str) {
+        let mut registry = self.registry();
+
+        if registry
+            .applications
+            .get(application)
+            .is_some_and(|metrics| metrics.in_flight == 0)
+        {
+            registry.applications.remove(application);
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry();
+        let mut output = String::new();
+
+        output.push_str(
+            "# HELP sail_requests_total Total number of requests handled per application.\n",
+        );
+        output.push_str("# TYPE sail_requests_total counter\n");
+        for (application, metrics) in registry.applications.iter() {
+            writeln!(
+                output,
+                "sail_requests_total{{application=\"{application}\"}} {}",
+                metrics.requests
+            )
+            .unwrap();
+        }
+
+        output.push_str("# HELP sail_responses_total Total number of responses per application and status class.\n");
+        output.push_str("# TYPE sail_responses_total counter\n");
+        for (application, metrics) in registry.applications.iter() {
+            for (class, count) in metrics.statuses.iter() {
+                writeln!(
+                    output,
+                    "sail_responses_total{{application=\"{application}\",class=\"{class}\"}} {count}"
+                )
+                .unwrap();
+            }
+        }
+
+        output.push_str("# HELP sail_requests_in_flight Number of requests currently being handled per application.\n");
+        output.push_str("# TYPE sail_requests_in_flight gauge\n");
+        for (application, metrics) in registry.applications.iter() {
+            writeln!(
+                output,
+                "sail_requests_in_flight{{application=\"{application}\"}} {}",
+                metrics.in_flight
+            )
+            .unwrap();
+        }
+
+        output.push_str("# HELP sail_request_duration_seconds Request latency per application.\n");
+        output.push_str("# TYPE sail_request_duration_seconds histogram\n");
+        for (application, metrics) in registry.applications.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(metrics.latency_buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    output,
+                    "sail_request_duration_seconds_bucket{{application=\"{application}\",le=\"{bound}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+
+            let count: u64 = metrics.statuses.values().sum();
+            writeln!(
+                output,
+                "sail_request_duration_seconds_bucket{{application=\"{application}\",le=\"+Inf\"}} {count}"
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "sail_request_duration_seconds_sum{{application=\"{application}\"}} {}",
+                metrics.latency_sum
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "sail_request_duration_seconds_count{{application=\"{application}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        output.push_str("# HELP sail_upstream_errors_total Total number of failed upstream fetches per error kind.\n");
+        output.push_str("# TYPE sail_upstream_errors_total counter\n");
+        for (kind, count) in registry.upstream_errors.iter() {
+            writeln!(
+                output,
+                "sail_upstream_errors_total{{kind=\"{kind}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        output.push_str("# HELP sail_control_requests_total Total number of control socket requests per request kind.\n");
+        output.push_str("# TYPE sail_control_requests_total counter\n");
+        for (request, count) in registry.control_requests.iter() {
+            writeln!(
+                output,
+                "sail_control_requests_total{{request=\"{request}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        output
+    }
+
+    fn with_application(&self, application: &str, f: impl FnOnce(&mut ApplicationMetrics)) {
+        f(self
+            .registry()
+            .applications
+            .entry(application.to_owned())
+            .or_default())
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry
+            .lock()
+            .expect("should be able to get lock on metrics registry")
+    }
+}
+
+/// Tracks a single in-flight request. Dropping it without calling [`Tracker::finish`] (for
+/// example when the client disconnects) only releases the in-flight gauge.
+pub struct Tracker {
+    metrics: Arc<Metrics>,
+    application: String,
+    start: Instant,
+}
+
+impl Tracker {
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn finish(&self, status: StatusCode) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let class = match status.as_u16() {
+            100..=199 => "1xx",
+            200..=299 => "2xx",
+            300..=399 => "3xx",
+            400..=499 => "4xx",
+            _ => "5xx",
+        };
+
+        self.metrics.with_application(&self.application, |metrics| {
+            *metrics.statuses.entry(class).or_default() += 1;
+            metrics.latency_sum += elapsed;
+
+            if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| elapsed <= *bound) {
+                metrics.latency_buckets[index] += 1;
+            }
+        });
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.metrics
+            .with_application(&self.application, |metrics| metrics.in_flight -= 1);
+    }
+}
+
+/// Bind the metrics listener on the loopback interface, unless a previous daemon handed it over.
+pub fn bind(port: u16, sockets: &mut ListenFds) -> io::Result<StdTcpListener> {
+    match sockets.take(SOCKET_NAME).pop() {
+        Some(fd) => Ok(StdTcpListener::from(fd)),
+        None => StdTcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port))),
+    }
+}
+
+/// Serve the metrics at `/metrics` until SIGTERM is received.
+pub async fn serve(metrics: Arc<Metrics>, listener: StdTcpListener) {
+    let listener = match listener
+        .set_nonblocking(true)
+        .and_then(|_| TcpListener::from_std(listener))
+    {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("setting up metrics listener failed: {e}");
+            return;
+        }
+    };
+
+    if let Ok(address) = listener.local_addr() {
+        info!("serving metrics on {address}");
+    }
+
+    let router = Router::new()
+        .route("/metrics", get(export))
+        .with_state(metrics);
+
+    let shutdown = async {
+        let mut sigterm = signal(SignalKind::terminate()).unwrap();
+        sigterm.recv().await;
+    };
+
+    if let Err(e) = axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        error!("metrics server failed: {e}")
+    }
+}
+
+/// Drop the metrics of applications when they are removed from the configuration, until SIGTERM
+/// is received.
+pub async fn watch(metrics: Arc<Metrics>, mut updates: broadcast::Receiver<Arc<Update>>) {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+
+    loop {
+        select! {
+            _ = sigterm.recv() => break,
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    for event in update.events.iter() {
+                        if let Event::ApplicationRemoved(application) = event {
+                            metrics.remove_application(&application.hostname);
+                        }
+                    }
+                }
+                // Metrics of applications removed meanwhile are kept, which is harmless.
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+async fn export(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
@@ -0,0 +1,97 @@
+use crate::configuration::{BackendKind, DEFAULT_ROOT};
+use core::fmt::{self, Display};
+use std::{env, error::Error, ffi::OsString, path::PathBuf};
+
+/// Environment variable overriding the configuration directory, unless `--config-dir` is given.
+const CONFIG_DIR_VARIABLE: &str = "SAIL_CONFIG_DIR";
+
+const USAGE: &str =
+    "Usage: saild [--config-dir <directory>] [--backend <filesystem|sqlite|memory>]";
+
+/// Command line options of the daemon.
+pub struct Options {
+    pub config_dir: PathBuf,
+    /// Where the configuration is stored, files in the configuration directory by default.
+    pub backend: BackendKind,
+}
+
+impl Options {
+    pub fn from_env() -> Result<Self, OptionsError> {
+        Self::parse(env::args_os().skip(1), env::var_os(CONFIG_DIR_VARIABLE))
+    }
+
+    fn parse(
+        arguments: impl IntoIterator<Item = OsString>,
+        config_dir: Option<OsString>,
+    ) -> Result<Self, OptionsError> {
+        let mut config_dir = config_dir.filter(|dir| !dir.is_empty()).map(PathBuf::from);
+        let mut backend = BackendKind::default();
+        let mut arguments = arguments.into_iter();
+
+        while let Some(argument) = arguments.next() {
+            let argument = argument.into_string().map_err(|argument| {
+                OptionsError::UnknownArgument(argument.to_string_lossy().into())
+            })?;
+
+            match argument.as_str() {
+                "--config-dir" => match arguments.next() {
+                    Some(dir) if !dir.is_empty() => config_dir = Some(dir.into()),
+                    _ => return Err(OptionsError::MissingValue(argument)),
+                },
+                "--backend" => match arguments.next() {
+                    Some(kind) => backend = parse_backend(&kind.to_string_lossy())?,
+                    None => return Err(OptionsError::MissingValue(argument)),
+                },
+                "-h" | "--help" => return Err(OptionsError::Help),
+                _ => {
+                    if let Some(kind) = argument.strip_prefix("--backend=") {
+                        backend = parse_backend(kind)?;
+                        continue;
+                    }
+
+                    match argument.strip_prefix("--config-dir=") {
+                        Some(dir) if !dir.is_empty() => config_dir = Some(dir.into()),
+                        Some(_) => return Err(OptionsError::MissingValue("--config-dir".into())),
+                        None => return Err(OptionsError::UnknownArgument(argument)),
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            config_dir: config_dir.unwrap_or_else(|| DEFAULT_ROOT.into()),
+            backend,
+        })
+    }
+}
+
+fn parse_backend(kind: &str) -> Result<BackendKind, OptionsError> {
+    kind.parse().map_err(OptionsError::UnknownBackend)
+}
+
+#[derive(Debug)]
+pub enum OptionsError {
+    Help,
+    MissingValue(String),
+    UnknownArgument(String),
+    UnknownBackend(String),
+}
+
+impl Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::Help => write!(f, "{USAGE}"),
+            OptionsError::MissingValue(option) => {
+                write!(f, "missing value for `{option}`\n{USAGE}")
+            }
+            OptionsError::UnknownArgument(argument) => {
+                write!(f, "unknown argument `{argument}`\n{USAGE}")
+            }
+            OptionsError::UnknownBackend(kind) => {
+                write!(f, "unknown backend `{kind}`\n{USAGE}")
+            }
+        }
+    }
+}
+
+impl Error for OptionsError {}
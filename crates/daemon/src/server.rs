@@ -1,80 +1,121 @@
+mod listener;
 mod proxy;
+mod proxy_protocol;
 
-use super::configuration::Configuration;
+use super::{configuration::Configuration, metrics::Metrics, systemd::ListenFds, upgrade::Handoff};
 use hyper::server::conn::http1::Builder as ConnectionBuilder;
-use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown, service::TowerToHyperService};
-use proxy::Proxy;
-use sail_config::Configurable;
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use hyper_util::server::graceful::GracefulShutdown;
+use listener::Listener;
+use sail_config::{events::Event, Configurable, ListenerConfiguration};
+use std::{
+    os::fd::{AsFd, OwnedFd},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    net::TcpListener,
     select,
     signal::unix::{signal, SignalKind},
-    sync::watch,
+    sync::{broadcast::error::RecvError, watch},
+    task::JoinHandle,
     time::sleep,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 pub struct Server {
     config: Arc<Configuration>,
+    metrics: Arc<Metrics>,
     http: ConnectionBuilder,
+    handoff: Handoff,
+    listeners: Vec<Listener>,
 }
 
 impl Server {
-    pub fn with_config(config: Arc<Configuration>) -> Self {
+    /// Set up all configured listeners, using sockets passed by systemd or a previous daemon
+    /// where available and binding the rest. The sockets are registered with `handoff`.
+    pub fn new(
+        config: Arc<Configuration>,
+        metrics: Arc<Metrics>,
+        mut sockets: ListenFds,
+        handoff: Handoff,
+    ) -> Self {
+        let mut listeners = Vec::new();
+
+        for configuration in config.get().core.listeners() {
+            let fds = sockets.take(&listener::socket_name(&configuration));
+
+            if fds.is_empty() {
+                let address = configuration.socket_address();
+                listeners.push(
+                    Listener::bind(configuration)
+                        .unwrap_or_else(|e| panic!("binding to {address} failed: {e}")),
+                );
+            } else {
+                for fd in fds {
+                    listeners.push(
+                        Listener::adopt(configuration.clone(), fd)
+                            .unwrap_or_else(|e| panic!("using inherited socket failed: {e}")),
+                    );
+                }
+            }
+        }
+
+        for name in sockets.remaining() {
+            warn!("ignoring inherited socket `{name}`, no listener is configured for it");
+        }
+
         Self {
             config,
+            metrics,
             http: ConnectionBuilder::new(),
+            handoff,
+            listeners,
         }
     }
 
-    pub async fn start(&self) {
-        let port = self.config.get().core.port;
-        let address = SocketAddr::from(([127, 0, 0, 1], port));
-        let listener = TcpListener::bind(address)
-            .await
-            .unwrap_or_else(|_| panic!("binding to localhost:{port} failed!"));
-
-        let (stop_tx, mut stop_rx) = watch::channel(());
+    /// Serve until SIGTERM is received, rebinding the listeners whenever they are changed in the
+    /// configuration.
+    pub async fn start(mut self) {
+        let mut sigterm = signal(SignalKind::terminate()).unwrap();
+        let mut updates = self.config.subscribe();
 
-        tokio::spawn(async move {
-            let mut sigterm = signal(SignalKind::terminate()).unwrap();
-            sigterm.recv().await;
-            stop_tx.send(()).unwrap();
-        });
+        let graceful = Arc::new(GracefulShutdown::new());
+        let mut running = Vec::new();
 
-        let graceful = GracefulShutdown::new();
+        for listener in std::mem::take(&mut self.listeners) {
+            running.push(self.run(listener, &graceful));
+        }
 
         loop {
-            select! {
-                biased;
-
-                _ = stop_rx.changed() => {
+            let listeners = select! {
+                _ = sigterm.recv() => {
                     info!("received SIGTERM signal!");
-                    break
+                    break;
+                }
+                update = updates.recv() => match update {
+                    Ok(update) => update.events.iter().find_map(|event| match event {
+                        Event::Listeners { new, .. } => Some(new.clone()),
+                        _ => None,
+                    }),
+                    // Some updates were missed, catch up with the current listeners.
+                    Err(RecvError::Lagged(_)) => Some(self.config.get().core.listeners()),
+                    Err(RecvError::Closed) => unreachable!("the configuration should outlive the server"),
                 },
-                Ok((stream, address)) = listener.accept() => {
-                    let configuration = self.config.clone();
-                    let http = self.http.clone();
-                    let io = TokioIo::new(stream);
-
-                    let proxy = TowerToHyperService::new(Proxy::new(configuration));
-
-                    info!("serving connection from {address}");
+            };
 
-                    let connection = http.serve_connection(io, proxy);
-                    let future = graceful.watch(connection);
+            let Some(listeners) = listeners else {
+                continue;
+            };
 
-                    tokio::spawn(async move {
-                        if let Err(error) = future.await {
-                            error!("Error while handling connection: {error:?}")
-                        }
-                    })
+            running = self.rebind(running, listeners, &graceful).await;
+        }
 
-                }
-            };
+        for listener in running {
+            listener.stop(&self.handoff).await;
         }
 
+        let graceful = Arc::into_inner(graceful)
+            .expect("all listeners should have stopped watching for connections");
+
         println!("HEY");
 
         tokio::select! {
@@ -87,3 +128,169 @@ impl Server {
         }
     }
 }
+
+impl Server {
+    fn run(&self, listener: Listener, graceful: &Arc<GracefulShutdown>) -> Running {
+        Running::start(
+            listener,
+            &self.config,
+            &self.metrics,
+            &self.http,
+            graceful,
+            &self.handoff,
+        )
+    }
+
+    /// Move from the `previous` listeners to the configured `listeners`. New sockets are set up
+    /// before the listeners they replace are stopped, so a listener that cannot be set up leaves
+    /// the previous one serving.
+    async fn rebind(
+        &self,
+        mut previous: Vec<Running>,
+        listeners: Vec<ListenerConfiguration>,
+        graceful: &Arc<GracefulShutdown>,
+    ) -> Vec<Running> {
+        let mut running = Vec::new();
+        let mut failed = Vec::new();
+
+        for configuration in listeners {
+            if let Some(index) = previous
+                .iter()
+                .position(|running| running.configuration == configuration)
+            {
+                running.push(previous.remove(index));
+            } else if let Some(index) = previous
+                .iter()
+                .position(|running| running.same_socket(&configuration))
+            {
+                // Only other settings changed, keep serving on the same socket.
+                let replaced = previous.remove(index);
+                let address = configuration.socket_address();
+
+                match replaced
+                    .socket
+                    .try_clone()
+                    .and_then(|fd| Listener::adopt(configuration, fd))
+                {
+                    Ok(listener) => {
+                        replaced.stop(&self.handoff).await;
+                        running.push(self.run(listener, graceful));
+                    }
+                    Err(e) => {
+                        error!("changing the listener on {address} failed, keeping its previous settings: {e}");
+                        running.push(replaced);
+                    }
+                }
+            } else {
+                match Listener::bind(configuration.clone()) {
+                    Ok(listener) => running.push(self.run(listener, graceful)),
+                    Err(e) => failed.push((configuration, e)),
+                }
+            }
+        }
+
+        let removed: Vec<ListenerConfiguration> = previous
+            .iter()
+            .map(|running| running.configuration.clone())
+            .collect();
+
+        for listener in previous {
+            listener.stop(&self.handoff).await;
+        }
+
+        // The removed listeners may have held the addresses, so try again now they are closed.
+        let mut unbound = false;
+        for (configuration, error) in failed {
+            let address = configuration.socket_address();
+            let retried = match removed.is_empty() {
+                true => Err(error),
+                false => Listener::bind(configuration),
+            };
+
+            match retried {
+                Ok(listener) => running.push(self.run(listener, graceful)),
+                Err(e) => {
+                    error!("listening on {address} failed: {e}");
+                    unbound = true;
+                }
+            }
+        }
+
+        // Rather than serving on fewer addresses, keep the removed listeners until the
+        // listeners are fixed.
+        if unbound {
+            for configuration in removed {
+                let address = configuration.socket_address();
+
+                match Listener::bind(configuration) {
+                    Ok(listener) => {
+                        warn!("still listening on {address}, which was removed, because other listeners failed");
+                        running.push(self.run(listener, graceful));
+                    }
+                    Err(e) => error!("listening on {address} again failed: {e}"),
+                }
+            }
+        }
+
+        running
+    }
+}
+
+/// A listener that is accepting connections.
+struct Running {
+    configuration: ListenerConfiguration,
+    /// A duplicate of the listening socket, to keep serving on it when only the settings of the
+    /// listener change.
+    socket: OwnedFd,
+    /// Id of the socket with the [`Handoff`].
+    handoff: u64,
+    stop: watch::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Running {
+    fn start(
+        listener: Listener,
+        config: &Arc<Configuration>,
+        metrics: &Arc<Metrics>,
+        http: &ConnectionBuilder,
+        graceful: &Arc<GracefulShutdown>,
+        handoff: &Handoff,
+    ) -> Self {
+        let (stop, stop_rx) = watch::channel(());
+
+        Self {
+            configuration: listener.configuration().clone(),
+            socket: listener
+                .as_fd()
+                .try_clone_to_owned()
+                .expect("should be able to duplicate listening socket"),
+            handoff: handoff.register(listener.socket_name(), listener.as_fd()),
+            stop,
+            task: tokio::spawn(listener.serve(
+                config.clone(),
+                metrics.clone(),
+                http.clone(),
+                graceful.clone(),
+                stop_rx,
+            )),
+        }
+    }
+
+    /// Whether `configuration` listens on the same socket, possibly with other settings.
+    fn same_socket(&self, configuration: &ListenerConfiguration) -> bool {
+        self.configuration.socket_address() == configuration.socket_address()
+            && self.configuration.ipv6_only == configuration.ipv6_only
+    }
+
+    /// Stop accepting connections and close the socket, the connections that were accepted are
+    /// still served.
+    async fn stop(self, handoff: &Handoff) {
+        handoff.unregister(self.handoff);
+        let _ = self.stop.send(());
+
+        if let Err(e) = self.task.await {
+            error!("listener failed: {e}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use super::{proxy::Proxy, proxy_protocol};
+use crate::{configuration::Configuration, metrics::Metrics};
+use hyper::server::conn::http1::Builder as ConnectionBuilder;
+use hyper_util::{
+    rt::TokioIo,
+    server::graceful::{GracefulShutdown, Watcher},
+    service::TowerToHyperService,
+};
+use sail_config::{ListenerConfiguration, ProxyProtocolConfiguration, TlsConfiguration};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::watch,
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::{error, info};
+
+const BACKLOG: i32 = 1024;
+
+pub struct Listener {
+    configuration: ListenerConfiguration,
+    tcp: TcpListener,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    pub fn bind(configuration: ListenerConfiguration) -> io::Result<Self> {
+        let address = configuration.socket_address();
+
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        if address.is_ipv6() {
+            socket.set_only_v6(configuration.ipv6_only)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into())?;
+        socket.listen(BACKLOG)?;
+
+        let tls = configuration.tls.as_ref().map(acceptor).transpose()?;
+
+        Ok(Self {
+            tcp: TcpListener::from_std(socket.into())?,
+            configuration,
+            tls,
+        })
+    }
+
+    /// Serve on a socket that was already bound, for example by systemd.
+    pub fn adopt(configuration: ListenerConfiguration, fd: OwnedFd) -> io::Result<Self> {
+        let listener = StdTcpListener::from(fd);
+        listener.set_nonblocking(true)?;
+
+        let tls = configuration.tls.as_ref().map(acceptor).transpose()?;
+
+        Ok(Self {
+            tcp: TcpListener::from_std(listener)?,
+            configuration,
+            tls,
+        })
+    }
+
+    pub fn configuration(&self) -> &ListenerConfiguration {
+        &self.configuration
+    }
+
+    pub fn socket_name(&self) -> String {
+        socket_name(&self.configuration)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.tcp
+            .local_addr()
+            .unwrap_or_else(|_| self.configuration.socket_address())
+    }
+
+    /// Accept connections until `stop` fires, registering each of them with `graceful`.
+    pub async fn serve(
+        self,
+        config: Arc<Configuration>,
+        metrics: Arc<Metrics>,
+        http: ConnectionBuilder,
+        graceful: Arc<GracefulShutdown>,
+        mut stop: watch::Receiver<()>,
+    ) {
+        info!(
+            "listening on {}{}",
+            self.address(),
+            if self.tls.is_some() { " (TLS)" } else { "" }
+        );
+
+        loop {
+            select! {
+                biased;
+
+                _ = stop.changed() => {
+                    info!("stopped listening on {}", self.address());
+                    break
+                },
+                Ok((stream, peer)) = self.tcp.accept() => {
+                    let connection = Connection {
+                        config: config.clone(),
+                        metrics: metrics.clone(),
+                        http: http.clone(),
+                        watcher: graceful.watcher(),
+                    };
+                    let proxy_protocol = self.configuration.proxy_protocol.clone();
+                    let tls = self.tls.clone();
+
+                    tokio::spawn(async move {
+                        connection.accept(stream, peer, proxy_protocol, tls).await
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.tcp.as_fd()
+    }
+}
+
+/// Name of the socket for a listener when it is passed by systemd or handed over during an
+/// upgrade: the configured name, or otherwise its address.
+pub fn socket_name(configuration: &ListenerConfiguration) -> String {
+    configuration
+        .name
+        .clone()
+        .unwrap_or_else(|| configuration.socket_address().to_string())
+}
+
+struct Connection {
+    config: Arc<Configuration>,
+    metrics: Arc<Metrics>,
+    http: ConnectionBuilder,
+    watcher: Watcher,
+}
+
+impl Connection {
+    async fn accept(
+        self,
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        proxy_protocol: Option<ProxyProtocolConfiguration>,
+        tls: Option<TlsAcceptor>,
+    ) {
+        // Behind a trusted load balancer, the client address is taken from the PROXY protocol
+        // header instead of the peer address.
+        let address = match proxy_protocol {
+            Some(proxy_protocol) if proxy_protocol.trusts(peer.ip()) => {
+                match proxy_protocol::read_header(&mut stream).await {
+                    Ok(address) => address.unwrap_or(peer),
+                    Err(e) => {
+                        error!("rejecting connection from {peer}: {e}");
+                        return;
+                    }
+                }
+            }
+            _ => peer,
+        };
+
+        match tls {
+            Some(acceptor) => match acceptor.accept(stream).await {
+                Ok(stream) => self.serve(stream, address, peer).await,
+                Err(e) => error!("TLS handshake with {address} failed: {e}"),
+            },
+            None => self.serve(stream, address, peer).await,
+        }
+    }
+
+    async fn serve<I>(self, stream: I, address: SocketAddr, peer: SocketAddr)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let io = TokioIo::new(stream);
+        let proxy = TowerToHyperService::new(Proxy::new(self.config, self.metrics, address));
+
+        info!("serving connection from {address} (peer {peer})");
+
+        let connection = self.http.serve_connection(io, proxy);
+
+        if let Err(error) = self.watcher.watch(connection).await {
+            error!("Error while handling connection: {error:?}")
+        }
+    }
+}
+
+fn acceptor(configuration: &TlsConfiguration) -> io::Result<TlsAcceptor> {
+    let certificates =
+        rustls_pemfile::certs(&mut BufReader::new(File::open(&configuration.certificate)?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&configuration.key)?))?
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no private key found in {}", configuration.key.display()),
+            )
+        })?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
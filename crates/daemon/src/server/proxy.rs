@@ -1,51 +1,66 @@
 mod body;
 mod fetcher;
+mod request_id;
 
+use crate::metrics::{Metrics, Tracker, UNKNOWN_APPLICATION, WEB_APPLICATION};
 use axum::routing::future::RouteFuture;
 use body::Body;
 use http_body_util::{Empty, Full};
 use hyper::{
     body::{Bytes, Incoming},
+    header::HeaderValue,
     Request, Response,
 };
+use opentelemetry::global;
+use opentelemetry_http::HeaderExtractor;
 use pin_project::pin_project;
+use request_id::RequestId;
 use sail_config::Configurable;
-use sail_core::proxy::{FetchError, ProxyError};
+use sail_core::proxy::{FetchError, ProxyError, REQUEST_ID_HEADER};
 use sail_web::WebInterface;
 use std::{
     convert::Infallible,
     future::Future,
+    net::SocketAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 use tower::Service;
-use tracing::{error, info};
+use tracing::{error, field, info, info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 const WEB_HOSTNAME: &str = "cabin.jensmeindertsma.com";
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
 
 pub struct Proxy<C> {
     configuration: Arc<C>,
+    metrics: Arc<Metrics>,
     web: WebInterface<C>,
+    client: SocketAddr,
 }
 
 impl<C> Clone for Proxy<C> {
     fn clone(&self) -> Self {
         Self {
             configuration: self.configuration.clone(),
+            metrics: self.metrics.clone(),
             web: self.web.clone(),
+            client: self.client,
         }
     }
 }
 
 impl<C> Proxy<C>
 where
-    C: Configurable,
+    C: Configurable + Send + Sync + 'static,
 {
-    pub fn new(configuration: Arc<C>) -> Self {
+    pub fn new(configuration: Arc<C>, metrics: Arc<Metrics>, client: SocketAddr) -> Self {
         Self {
             web: WebInterface::new(configuration.clone()),
             configuration,
+            metrics,
+            client,
         }
     }
 }
@@ -62,7 +77,46 @@ where
         <WebInterface<C> as Service<Request<Incoming>>>::poll_ready(&mut self.web, context)
     }
 
-    fn call(&mut self, request: Request<Incoming>) -> Self::Future {
+    fn call(&mut self, mut request: Request<Incoming>) -> Self::Future {
+        let request_id = RequestId::from_headers(request.headers());
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, request_id.header_value());
+
+        let span = info_span!(
+            "proxy",
+            request_id = %request_id,
+            client = %self.client,
+            method = %request.method(),
+            uri = %request.uri(),
+            status = field::Empty,
+        );
+
+        // Continue the trace of the client, if it sent a `traceparent` header.
+        span.set_parent(global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        }));
+
+        let (state, tracker) = span.in_scope(|| self.route(request, &request_id));
+
+        ProxyFuture {
+            state,
+            tracker,
+            span,
+            request_id,
+        }
+    }
+}
+
+impl<C> Proxy<C>
+where
+    C: Configurable,
+{
+    fn route(
+        &mut self,
+        mut request: Request<Incoming>,
+        request_id: &RequestId,
+    ) -> (State<C>, Tracker) {
         let host_header = request
             .headers()
             .get("Host")
@@ -73,60 +127,91 @@ where
         match host_header {
             Some(host) if host.as_str() == WEB_HOSTNAME => {
                 info!("request is to web interface");
-                ProxyFuture::Web(self.web.call(request))
+                (
+                    State::Web(self.web.call(request)),
+                    self.metrics.track(WEB_APPLICATION),
+                )
             }
             Some(host) => {
-                if let Some(address) =
-                    self.configuration
-                        .get()
-                        .applications
-                        .iter()
-                        .find_map(|app| {
-                            if app.hostname == host {
-                                Some(app.address)
-                            } else {
-                                None
-                            }
-                        })
-                {
+                let routes = self.configuration.routes();
+
+                if let Some(route) = routes.route(&host) {
                     info!("request is to proxied application");
 
-                    ProxyFuture::Forwarded {
-                        future: Box::pin(fetcher::fetch(address, request)),
-                        web: self.web.clone(),
-                    }
+                    forward_client(&mut request, self.client);
+
+                    (
+                        State::Forwarded {
+                            future: Box::pin(fetcher::fetch(route.address, request)),
+                            web: self.web.clone(),
+                        },
+                        self.metrics.track(&route.hostname),
+                    )
                 } else {
                     info!("request is to unknown proxy address");
 
-                    ProxyFuture::Web(
-                        self.web.call(
-                            Request::builder()
-                                .uri("/proxy-error")
-                                .header("Host", host)
-                                .body(Empty::new())
-                                .expect("constructing error page request should succeed"),
+                    (
+                        State::Web(
+                            self.web.call(
+                                Request::builder()
+                                    .uri("/proxy-error")
+                                    .header("Host", host)
+                                    .header(REQUEST_ID_HEADER, request_id.header_value())
+                                    .body(Empty::new())
+                                    .expect("constructing error page request should succeed"),
+                            ),
                         ),
+                        self.metrics.track(UNKNOWN_APPLICATION),
                     )
                 }
             }
             None => {
                 info!("request has no Host header");
 
-                ProxyFuture::Web(
-                    self.web.call(
-                        Request::builder()
-                            .uri("/proxy-error")
-                            .body(Empty::new())
-                            .expect("constructing error page request should succeed"),
+                (
+                    State::Web(
+                        self.web.call(
+                            Request::builder()
+                                .uri("/proxy-error")
+                                .header(REQUEST_ID_HEADER, request_id.header_value())
+                                .body(Empty::new())
+                                .expect("constructing error page request should succeed"),
+                        ),
                     ),
+                    self.metrics.track(UNKNOWN_APPLICATION),
                 )
             }
         }
     }
 }
 
+/// Append the client address to the `X-Forwarded-For` header of a request to an application.
+fn forward_client<B>(request: &mut Request<B>, client: SocketAddr) {
+    let client = client.ip().to_canonical();
+
+    let forwarded_for = match request.headers().get(FORWARDED_FOR_HEADER) {
+        Some(existing) => format!("{}, {client}", existing.to_str().unwrap_or_default()),
+        None => client.to_string(),
+    };
+
+    request.headers_mut().insert(
+        FORWARDED_FOR_HEADER,
+        HeaderValue::from_str(&forwarded_for).expect("addresses should be valid header values"),
+    );
+}
+
+#[pin_project]
+pub struct ProxyFuture<C> {
+    #[pin]
+    state: State<C>,
+    tracker: Tracker,
+    span: Span,
+    request_id: RequestId,
+}
+
 #[pin_project(project = Enum)]
-pub enum ProxyFuture<C> {
+#[allow(clippy::large_enum_variant)]
+enum State<C> {
     Forwarded {
         #[pin]
         future:
@@ -144,20 +229,23 @@ where
 
     fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.as_mut().project();
+        let _entered = this.span.enter();
 
         enum Outcome<C> {
             Poll(Poll<Result<Response<Body>, Infallible>>),
-            Mutate(ProxyFuture<C>),
+            Mutate(State<C>),
         }
 
         info!("polling proxy future");
 
-        let outcome: Outcome<C> = match this {
+        let outcome: Outcome<C> = match this.state.project() {
             Enum::Forwarded { mut future, web } => match future.as_mut().poll(context) {
                 Poll::Ready(result) => match result {
                     Ok(response) => Outcome::Poll(Poll::Ready(Ok(response.map(Body::Hyper)))),
                     Err(fetch_error) => {
                         error!("fetcher returned error: {:?}", fetch_error);
+                        this.tracker.metrics().record_upstream_error(&fetch_error);
+
                         let error = serde_json::to_string(&ProxyError::FetchError(fetch_error))
                             .expect("serialization of proxy error should succeed")
                             .as_bytes()
@@ -166,6 +254,7 @@ where
                         let request = Request::builder()
                             .uri("/proxy-error")
                             .header("Content-Type", "application/json")
+                            .header(REQUEST_ID_HEADER, this.request_id.header_value())
                             .body(Full::new(Bytes::from(error)))
                             .expect("constructing error page request should succeed");
 
@@ -173,7 +262,7 @@ where
 
                         let web_future = web.call(request);
 
-                        Outcome::Mutate(ProxyFuture::Web(web_future))
+                        Outcome::Mutate(State::Web(web_future))
                     }
                 },
                 Poll::Pending => Outcome::Poll(Poll::Pending),
@@ -185,19 +274,29 @@ where
         };
 
         match outcome {
-            Outcome::Poll(poll) => {
-                match poll {
-                    Poll::Pending => {
-                        info!("proxy futured polled pending");
-                    }
-                    Poll::Ready(_) => {
-                        info!("proxy futured polled ready");
-                    }
+            Outcome::Poll(poll) => match poll {
+                Poll::Pending => {
+                    info!("proxy futured polled pending");
+                    Poll::Pending
                 }
-                poll
-            }
+                Poll::Ready(result) => {
+                    info!("proxy futured polled ready");
+
+                    Poll::Ready(result.map(|mut response| {
+                        response
+                            .headers_mut()
+                            .insert(REQUEST_ID_HEADER, this.request_id.header_value());
+
+                        this.span.record("status", response.status().as_u16());
+                        this.tracker.finish(response.status());
+
+                        response
+                    }))
+                }
+            },
             Outcome::Mutate(value) => {
-                *self = value;
+                drop(_entered);
+                self.as_mut().project().state.set(value);
                 info!("mutated proxy future");
                 self.poll(context)
             }
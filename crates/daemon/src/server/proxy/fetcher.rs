@@ -2,17 +2,28 @@ use std::net::SocketAddr;
 
 use hyper::{body::Incoming, Request, Response};
 use hyper_util::rt::TokioIo;
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
 use sail_core::proxy::FetchError;
 use tokio::net::TcpStream;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 #[instrument(skip(request))]
 pub async fn fetch(
     address: SocketAddr,
-    request: Request<Incoming>,
+    mut request: Request<Incoming>,
 ) -> Result<Response<Incoming>, FetchError> {
     info!("fetching {address} uri: {}", request.uri());
 
+    // Hand the trace context of this hop to the application.
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &Span::current().context(),
+            &mut HeaderInjector(request.headers_mut()),
+        )
+    });
+
     let stream = TcpStream::connect(address)
         .await
         .map_err(|e| FetchError::Connection(e.to_string()))?;
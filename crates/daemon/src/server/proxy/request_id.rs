@@ -0,0 +1,54 @@
+use core::fmt::{self, Display};
+use hyper::{header::HeaderValue, HeaderMap};
+use rand::Rng;
+use sail_core::proxy::REQUEST_ID_HEADER;
+
+const MAX_LENGTH: usize = 128;
+
+/// Unique ID of a request, reused from the `X-Request-Id` header when the client (or a proxy in
+/// front of us) already assigned one.
+#[derive(Clone, Debug)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        match headers.get(REQUEST_ID_HEADER) {
+            // Incoming IDs end up in logs and error pages, so only accept a conservative set of
+            // characters.
+            Some(value)
+                if !value.is_empty()
+                    && value.len() <= MAX_LENGTH
+                    && value
+                        .as_bytes()
+                        .iter()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(b)) =>
+            {
+                Self(value.clone())
+            }
+            _ => Self::generate(),
+        }
+    }
+
+    fn generate() -> Self {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        let id: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+
+        Self(HeaderValue::from_str(&id).expect("hexadecimal string should be valid header value"))
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        self.0.clone()
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.0
+                .to_str()
+                .expect("request ID should only contain visible ASCII")
+        )
+    }
+}
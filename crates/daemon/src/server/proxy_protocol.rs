@@ -0,0 +1,371 @@
+use core::fmt::{self, Display};
+use std::{
+    error::Error,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// Signature that starts every version 2 header.
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// Maximum length of a version 1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Length of the fixed part of a version 2 header: signature, version/command, family and length.
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Number of bytes read before deciding which version of the header is sent.
+const PREFIX_LENGTH: usize = 6;
+
+/// How long a trusted peer gets to send its complete header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read a PROXY protocol (version 1 or 2) header from the start of `stream`, returning the
+/// address of the original client. Returns `None` when the header does not carry an address,
+/// like health checks sent by the load balancer itself (`LOCAL` / `UNKNOWN`).
+///
+/// Only the header is consumed, so the rest of the stream can be handed to the HTTP server.
+pub async fn read_header(stream: &mut TcpStream) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    timeout(HEADER_TIMEOUT, read(stream))
+        .await
+        .map_err(|_| ProxyProtocolError::Timeout)?
+}
+
+async fn read(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    // Both versions are longer than this prefix, which is enough to tell them apart.
+    let mut prefix = [0; PREFIX_LENGTH];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == *b"PROXY " {
+        read_v1(stream, prefix).await
+    } else if prefix == V2_SIGNATURE[..PREFIX_LENGTH] {
+        read_v2(stream, prefix).await
+    } else {
+        Err(ProxyProtocolError::MissingHeader)
+    }
+}
+
+async fn read_v1(
+    stream: &mut (impl AsyncRead + Unpin),
+    prefix: [u8; PREFIX_LENGTH],
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    // The header is terminated by CRLF, read byte by byte so nothing beyond it is consumed.
+    let mut header = Vec::with_capacity(V1_MAX_LENGTH);
+    header.extend_from_slice(&prefix);
+
+    while !header.ends_with(b"\r\n") {
+        if header.len() == V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::Malformed("version 1 header too long"));
+        }
+
+        header.push(stream.read_u8().await?);
+    }
+
+    let header = std::str::from_utf8(&header[..header.len() - 2])
+        .map_err(|_| ProxyProtocolError::Malformed("version 1 header is not ASCII"))?;
+
+    let mut parts = header.split(' ').skip(1);
+
+    let parse_address = |part: Option<&str>| -> Result<IpAddr, ProxyProtocolError> {
+        part.and_then(|p| p.parse().ok())
+            .ok_or(ProxyProtocolError::Malformed(
+                "invalid address in version 1 header",
+            ))
+    };
+    let parse_port = |part: Option<&str>| -> Result<u16, ProxyProtocolError> {
+        part.and_then(|p| p.parse().ok())
+            .ok_or(ProxyProtocolError::Malformed(
+                "invalid port in version 1 header",
+            ))
+    };
+
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {
+            let source = parse_address(parts.next())?;
+            let _destination = parse_address(parts.next())?;
+            let source_port = parse_port(parts.next())?;
+            let _destination_port = parse_port(parts.next())?;
+
+            Ok(Some(SocketAddr::new(source, source_port)))
+        }
+        Some("UNKNOWN") => Ok(None),
+        _ => Err(ProxyProtocolError::Malformed(
+            "unsupported protocol in version 1 header",
+        )),
+    }
+}
+
+async fn read_v2(
+    stream: &mut (impl AsyncRead + Unpin),
+    prefix: [u8; PREFIX_LENGTH],
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    let mut header = [0; V2_HEADER_LENGTH];
+    header[..PREFIX_LENGTH].copy_from_slice(&prefix);
+    stream.read_exact(&mut header[PREFIX_LENGTH..]).await?;
+
+    if header[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+        return Err(ProxyProtocolError::MissingHeader);
+    }
+
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0F;
+    let family = header[13];
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+
+    if version != 2 {
+        return Err(ProxyProtocolError::Malformed(
+            "unsupported version 2 header version",
+        ));
+    }
+
+    let mut addresses = vec![0; length];
+    stream.read_exact(&mut addresses).await?;
+
+    match command {
+        // LOCAL: the connection was made by the load balancer itself.
+        0x0 => Ok(None),
+        // PROXY
+        0x1 => match family {
+            // TCP over IPv4
+            0x11 if length >= 12 => {
+                let source = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+                Ok(Some(SocketAddr::new(source.into(), port)))
+            }
+            // TCP over IPv6
+            0x21 if length >= 36 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[..16]);
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+                Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+            }
+            0x11 | 0x21 => Err(ProxyProtocolError::Malformed(
+                "address block of version 2 header too short",
+            )),
+            // UNSPEC, UDP and Unix sockets carry no usable client address.
+            _ => Ok(None),
+        },
+        _ => Err(ProxyProtocolError::Malformed(
+            "unsupported version 2 command",
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyProtocolError {
+    Io(io::Error),
+    MissingHeader,
+    Malformed(&'static str),
+    Timeout,
+}
+
+impl From<io::Error> for ProxyProtocolError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyProtocolError::Io(e) => write!(f, "failed to read PROXY protocol header: {e}"),
+            ProxyProtocolError::MissingHeader => write!(f, "missing PROXY protocol header"),
+            ProxyProtocolError::Malformed(reason) => {
+                write!(f, "malformed PROXY protocol header: {reason}")
+            }
+            ProxyProtocolError::Timeout => write!(f, "timed out reading PROXY protocol header"),
+        }
+    }
+}
+
+impl Error for ProxyProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a version 2 header with `command`, `family` and the address block `addresses`.
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    /// Read a header from `input`, returning the result and what was left unread.
+    async fn parse(input: &[u8]) -> (Result<Option<SocketAddr>, ProxyProtocolError>, &[u8]) {
+        let mut stream = input;
+        let result = read(&mut stream).await;
+        (result, stream)
+    }
+
+    #[tokio::test]
+    async fn v1() {
+        let (result, rest) = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (result, _) = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+        assert_eq!(
+            result.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        let (result, _) = parse(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        for input in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 70000\r\n",
+            b"PROXY TCP4 example.com 198.51.100.1 56324 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+        ] {
+            let (result, _) = parse(input).await;
+            assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut input = b"PROXY TCP4 ".to_vec();
+        input.resize(V1_MAX_LENGTH + 10, b'1');
+        input.extend_from_slice(b"\r\n");
+
+        let (result, _) = parse(&input).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn v2_ipv4() {
+        let mut input = v2(
+            0x1,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        input.extend_from_slice(b"GET /");
+
+        let (result, rest) = parse(&input).await;
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn v2_ipv6() {
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+
+        let mut addresses = Vec::new();
+        addresses.extend_from_slice(&source.octets());
+        addresses.extend_from_slice(&destination.octets());
+        addresses.extend_from_slice(&56324u16.to_be_bytes());
+        addresses.extend_from_slice(&443u16.to_be_bytes());
+
+        let input = v2(0x1, 0x21, &addresses);
+        let (result, rest) = parse(&input).await;
+        assert_eq!(
+            result.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let input = v2(0x0, 0x00, &[]);
+        let (result, rest) = parse(&input).await;
+        assert_eq!(result.unwrap(), None);
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_extra_addresses_are_consumed() {
+        // Type-length-value fields after the addresses are part of the header.
+        let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB];
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+
+        let input = v2(0x1, 0x11, &addresses);
+        let (result, rest) = parse(&input).await;
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_short_address_block() {
+        let (result, _) = parse(&v2(0x1, 0x11, &[192, 0, 2, 1])).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+
+        let (result, _) = parse(&v2(0x1, 0x21, &[0; 12])).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn v2_oversized_length() {
+        // The length claims more than is sent.
+        let mut input = v2(
+            0x1,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        input[14..16].copy_from_slice(&u16::MAX.to_be_bytes());
+
+        let (result, _) = parse(&input).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn v2_unsupported() {
+        let mut input = v2(0x1, 0x11, &[0; 12]);
+        input[12] = 0x11;
+        let (result, _) = parse(&input).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+
+        let (result, _) = parse(&v2(0x2, 0x11, &[0; 12])).await;
+        assert!(matches!(result, Err(ProxyProtocolError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        for input in [
+            &b"PROX"[..],
+            b"PROXY TCP4 192.0.2.1",
+            &V2_SIGNATURE[..8],
+            &v2(
+                0x1,
+                0x11,
+                &[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB],
+            )[..20],
+        ] {
+            let (result, _) = parse(input).await;
+            assert!(matches!(result, Err(ProxyProtocolError::Io(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_header() {
+        let (result, _) = parse(b"GET / HTTP/1.1\r\n").await;
+        assert!(matches!(result, Err(ProxyProtocolError::MissingHeader)));
+
+        let mut input = V2_SIGNATURE.to_vec();
+        input[8] = 0;
+        input.extend_from_slice(&[0x21, 0x11, 0, 0]);
+        let (result, _) = parse(&input).await;
+        assert!(matches!(result, Err(ProxyProtocolError::MissingHeader)));
+    }
+}
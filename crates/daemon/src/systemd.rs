@@ -0,0 +1,125 @@
+use std::{
+    env,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+};
+use tracing::{error, info, warn};
+
+/// The first file descriptor passed by systemd, see `sd_listen_fds(3)`.
+const LISTEN_FDS_START: i32 = 3;
+
+/// The variables systemd passes sockets with, see `sd_listen_fds(3)`.
+pub struct ListenEnv {
+    pid: Option<String>,
+    count: Option<String>,
+    names: Option<String>,
+}
+
+impl ListenEnv {
+    /// Read and remove `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`, so they don't leak into
+    /// child processes. Only call this before other threads are started, which may read the
+    /// environment while it is changed.
+    pub fn take() -> Self {
+        let taken = Self {
+            pid: env::var("LISTEN_PID").ok(),
+            count: env::var("LISTEN_FDS").ok(),
+            names: env::var("LISTEN_FDNAMES").ok(),
+        };
+
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        taken
+    }
+}
+
+/// Sockets passed to the daemon by systemd socket activation, identified by the
+/// `FileDescriptorName=` of their socket unit (which defaults to the name of the unit).
+pub struct ListenFds {
+    fds: Vec<(String, OwnedFd)>,
+}
+
+impl ListenFds {
+    /// Take ownership of the sockets passed through `LISTEN_FDS`, `LISTEN_FDNAMES` and
+    /// `LISTEN_PID`.
+    pub fn from_env(env: ListenEnv) -> Self {
+        let ListenEnv { pid, count, names } = env;
+
+        let Some(count) = count else {
+            warn!("no sockets passed by systemd (missing LISTEN_FDS)");
+            return Self { fds: Vec::new() };
+        };
+
+        if let Some(pid) = pid {
+            if pid.parse::<u32>().ok() != Some(process::id()) {
+                warn!("ignoring sockets passed by systemd for another process (LISTEN_PID={pid})");
+                return Self { fds: Vec::new() };
+            }
+        }
+
+        let count: i32 = count.parse().expect("LISTEN_FDS should be a valid integer");
+
+        info!("LISTEN_FDS={count} LISTEN_FDNAMES={names:?}");
+
+        let mut names = names
+            .as_deref()
+            .unwrap_or_default()
+            .split(':')
+            .map(str::to_owned);
+
+        let fds = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                let name = names
+                    .next()
+                    .filter(|n| !n.is_empty())
+                    .unwrap_or_else(|| "unknown".into());
+
+                // SAFETY: this comes from systemd, which hands us ownership of these sockets.
+                (name, unsafe { OwnedFd::from_raw_fd(fd) })
+            })
+            .collect();
+
+        Self { fds }
+    }
+
+    pub fn from_fds(fds: Vec<(String, OwnedFd)>) -> Self {
+        Self { fds }
+    }
+
+    /// Remove and return all sockets whose name is `name`.
+    pub fn take(&mut self, name: &str) -> Vec<OwnedFd> {
+        let (taken, remaining) = self.fds.drain(..).partition(|(n, _)| n == name);
+        self.fds = remaining;
+
+        taken.into_iter().map(|(_, fd)| fd).collect()
+    }
+
+    /// Names of the sockets that have not been taken.
+    pub fn remaining(&self) -> impl Iterator<Item = &str> {
+        self.fds.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Send a state change to the service manager, see `sd_notify(3)`. Does nothing when the
+/// daemon is not run by systemd.
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(&path),
+    };
+
+    if let Err(e) = address
+        .and_then(|address| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address))
+    {
+        error!("failed to notify systemd of `{state}`: {e}")
+    }
+}
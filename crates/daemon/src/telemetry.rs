@@ -0,0 +1,75 @@
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime::Tokio, trace::TracerProvider, Resource,
+};
+use std::env;
+use tracing::{level_filters::LevelFilter, Level};
+use tracing_subscriber::{
+    filter::Targets, fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+};
+
+/// Environment variable holding the OTLP (gRPC) collector endpoint, for example
+/// `http://localhost:4317`. Spans are only exported when it is set.
+const ENDPOINT_VARIABLE: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+const SERVICE_NAME: &str = "saild";
+
+/// Target of the spans of the daemon, its crate name.
+const TARGET: &str = env!("CARGO_CRATE_NAME");
+
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Telemetry {
+    /// Install the global tracing subscriber, exporting spans to the configured OTLP collector
+    /// if there is one.
+    pub fn init() -> Self {
+        // W3C `traceparent`/`tracestate` are used to propagate trace context to and from
+        // applications.
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let provider = env::var(ENDPOINT_VARIABLE).ok().map(|endpoint| {
+            let exporter = SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()
+                .expect("building the OTLP span exporter should succeed");
+
+            TracerProvider::builder()
+                .with_batch_exporter(exporter, Tokio)
+                .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+                .build()
+        });
+
+        // Only export the spans of the daemon itself. The libraries below it, like the gRPC
+        // client that exports spans, would otherwise create spans for every export.
+        let layer = provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(SERVICE_NAME))
+                .with_filter(
+                    Targets::new()
+                        .with_target(TARGET, Level::TRACE)
+                        .with_default(LevelFilter::OFF),
+                )
+        });
+
+        tracing_subscriber::registry()
+            .with(LevelFilter::TRACE)
+            .with(fmt::layer())
+            .with(layer)
+            .init();
+
+        Self { provider }
+    }
+
+    /// Flush any spans that have not been exported yet.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("failed to shut down tracer provider: {e}")
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::systemd::ListenFds;
+use core::fmt::{self, Display};
+use nix::{
+    cmsg_space,
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    sys::{
+        signal::{raise, Signal},
+        socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr},
+    },
+};
+use std::{
+    env,
+    error::Error,
+    ffi::OsString,
+    io::{self, IoSlice, IoSliceMut, Write},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream as StdUnixStream,
+    },
+    process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::UnixStream,
+    select,
+    signal::unix::{signal, SignalKind},
+    time::timeout,
+};
+use tracing::{error, info};
+
+/// Environment variable telling a freshly started daemon which inherited file descriptor
+/// connects it to the daemon it is replacing.
+const UPGRADE_FD_VARIABLE: &str = "SAIL_UPGRADE_FD";
+
+/// Maximum number of sockets that can be handed over.
+const MAX_SOCKETS: usize = 64;
+
+/// Maximum size of the list of socket names.
+const MAX_NAMES_LENGTH: usize = 64 * 1024;
+
+/// How long the new daemon gets to start serving before the upgrade is aborted.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Byte sent by the new daemon once it is serving on the inherited sockets.
+const READY: u8 = b'1';
+
+/// The listening sockets of this daemon, kept so they can be handed over to its successor.
+/// Clones share the sockets, so listeners that are rebound can update them.
+#[derive(Clone, Default)]
+pub struct Handoff {
+    sockets: Arc<Mutex<Vec<Registered>>>,
+}
+
+struct Registered {
+    id: u64,
+    name: String,
+    fd: OwnedFd,
+}
+
+impl Handoff {
+    /// Keep a duplicate of `fd` to hand over under `name`, returning an id to unregister it with.
+    pub fn register(&self, name: impl Into<String>, fd: BorrowedFd<'_>) -> u64 {
+        let fd = fd
+            .try_clone_to_owned()
+            .expect("should be able to duplicate listening socket");
+
+        let mut sockets = self.sockets();
+        let id = sockets.last().map_or(0, |socket| socket.id + 1);
+        sockets.push(Registered {
+            id,
+            name: name.into(),
+            fd,
+        });
+
+        id
+    }
+
+    /// Stop handing over a socket, closing the duplicate kept of it.
+    pub fn unregister(&self, id: u64) {
+        self.sockets().retain(|socket| socket.id != id);
+    }
+
+    fn sockets(&self) -> std::sync::MutexGuard<'_, Vec<Registered>> {
+        self.sockets
+            .lock()
+            .expect("should be able to get lock on handed over sockets")
+    }
+
+    /// Upgrade to the binary that is currently installed whenever SIGUSR2 is received, until
+    /// SIGTERM is received.
+    ///
+    /// The new daemon is started with the listening sockets of this one. Once it reports that it
+    /// is serving, this daemon stops accepting connections and drains like on SIGTERM.
+    pub async fn listen(self) {
+        let mut sigusr2 = signal(SignalKind::user_defined2()).unwrap();
+        let mut sigterm = signal(SignalKind::terminate()).unwrap();
+
+        loop {
+            select! {
+                _ = sigterm.recv() => break,
+                _ = sigusr2.recv() => {
+                    info!("received SIGUSR2 signal, upgrading");
+
+                    match self.upgrade().await {
+                        Ok(()) => {
+                            info!("new daemon is serving, shutting down");
+
+                            // Every component stops on SIGTERM, so drain exactly like a regular
+                            // shutdown.
+                            raise(Signal::SIGTERM).expect("should be able to raise SIGTERM");
+                            break;
+                        }
+                        Err(e) => error!("upgrade failed, continuing to serve: {e}"),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn upgrade(&self) -> Result<(), UpgradeError> {
+        let (parent, child) = StdUnixStream::pair()?;
+
+        // The child end has to survive `exec`.
+        fcntl(child.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty()))?;
+
+        // Prefer the path we were started with: when the binary has been replaced, the path of
+        // the running executable points to the deleted file.
+        let mut arguments: Vec<OsString> = env::args_os().collect();
+        let program = match arguments.is_empty() {
+            false => arguments.remove(0),
+            true => env::current_exe()?.into_os_string(),
+        };
+
+        let mut process = Command::new(program)
+            .args(arguments)
+            .env(UPGRADE_FD_VARIABLE, child.as_raw_fd().to_string())
+            .spawn()?;
+
+        drop(child);
+
+        info!("started new daemon with pid {}", process.id());
+
+        // Duplicate the sockets, so they stay open if a listener is rebound meanwhile.
+        let sockets = self
+            .sockets()
+            .iter()
+            .map(|socket| Ok((socket.name.clone(), socket.fd.try_clone()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let names = serde_json::to_vec(
+            &sockets
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .expect("serialization of socket names should succeed");
+
+        let fds: Vec<RawFd> = sockets.iter().map(|(_, fd)| fd.as_raw_fd()).collect();
+
+        let result = async {
+            sendmsg::<UnixAddr>(
+                parent.as_raw_fd(),
+                &[IoSlice::new(&names)],
+                &[ControlMessage::ScmRights(&fds)],
+                MsgFlags::empty(),
+                None,
+            )?;
+
+            parent.set_nonblocking(true)?;
+            let mut parent = UnixStream::from_std(parent)?;
+
+            match timeout(READY_TIMEOUT, parent.read_u8()).await {
+                Ok(Ok(READY)) => Ok(()),
+                Ok(Ok(_)) => Err(UpgradeError::Protocol),
+                Ok(Err(e)) => Err(e.into()),
+                Err(_) => Err(UpgradeError::Timeout),
+            }
+        }
+        .await;
+
+        if result.is_err() {
+            // Never leave two daemons running side by side.
+            if let Err(e) = process.kill().and_then(|_| process.wait()) {
+                error!("failed to stop new daemon: {e}")
+            }
+        }
+
+        result
+    }
+}
+
+/// Connection to the daemon this one is replacing.
+pub struct Predecessor {
+    stream: StdUnixStream,
+}
+
+impl Predecessor {
+    /// Take the connection to the daemon this one is replacing, if it was started by an upgrade.
+    /// The variable naming it is removed, so only call this before other threads are started,
+    /// which may read the environment while it is changed.
+    pub fn connect() -> Option<Self> {
+        let fd = env::var(UPGRADE_FD_VARIABLE).ok();
+        env::remove_var(UPGRADE_FD_VARIABLE);
+
+        let fd: RawFd = fd?.parse().ok()?;
+
+        // SAFETY: the previous daemon passed this end of the socket pair to us.
+        let stream = unsafe { StdUnixStream::from_raw_fd(fd) };
+
+        Some(Self { stream })
+    }
+
+    /// Receive the listening sockets of the daemon this one is replacing.
+    pub fn inherit(&self) -> Result<ListenFds, UpgradeError> {
+        let mut names = vec![0; MAX_NAMES_LENGTH];
+        let mut iov = [IoSliceMut::new(&mut names)];
+        let mut buffer = cmsg_space!([RawFd; MAX_SOCKETS]);
+
+        let message = recvmsg::<UnixAddr>(
+            self.stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+
+        // Own whatever was received first, so it is closed on errors.
+        let mut fds = Vec::new();
+        for message in message.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(received) = message {
+                // SAFETY: these sockets were just handed to us by the previous daemon.
+                fds.extend(
+                    received
+                        .into_iter()
+                        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+                );
+            }
+        }
+
+        if message
+            .flags
+            .intersects(MsgFlags::MSG_CTRUNC | MsgFlags::MSG_TRUNC)
+        {
+            return Err(UpgradeError::Truncated);
+        }
+
+        let length = message.bytes;
+        let names: Vec<String> =
+            serde_json::from_slice(&names[..length]).map_err(|_| UpgradeError::Protocol)?;
+
+        if names.len() != fds.len() {
+            return Err(UpgradeError::Mismatch {
+                sockets: fds.len(),
+                names: names.len(),
+            });
+        }
+
+        info!("inherited sockets {names:?} from previous daemon");
+
+        Ok(ListenFds::from_fds(names.into_iter().zip(fds).collect()))
+    }
+
+    /// Tell the previous daemon that we are serving, so it can shut down.
+    pub fn ready(mut self) {
+        if let Err(e) = self.stream.write_all(&[READY]) {
+            error!("failed to notify previous daemon: {e}")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UpgradeError {
+    Io(io::Error),
+    Protocol,
+    Timeout,
+    /// More sockets or names were handed over than fit.
+    Truncated,
+    Mismatch {
+        sockets: usize,
+        names: usize,
+    },
+}
+
+impl From<io::Error> for UpgradeError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<nix::Error> for UpgradeError {
+    fn from(error: nix::Error) -> Self {
+        Self::Io(error.into())
+    }
+}
+
+impl Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeError::Io(e) => write!(f, "{e}"),
+            UpgradeError::Protocol => write!(f, "unexpected message from other daemon"),
+            UpgradeError::Timeout => write!(f, "new daemon did not become ready in time"),
+            UpgradeError::Truncated => write!(f, "too many sockets handed over"),
+            UpgradeError::Mismatch { sockets, names } => {
+                write!(f, "received {sockets} sockets but {names} names")
+            }
+        }
+    }
+}
+
+impl Error for UpgradeError {}
@@ -1,12 +1,13 @@
 use axum::{
     body::{Body, Bytes, HttpBody},
     response::{Html, IntoResponse},
-    routing::future::RouteFuture,
+    routing::{future::RouteFuture, get},
     BoxError, Json, Router,
 };
-use http::Uri;
+use http::{HeaderMap, Uri};
 use hyper::{Request, Response};
 use sail_config::Configurable;
+use sail_core::proxy::REQUEST_ID_HEADER;
 use std::{
     convert::Infallible,
     sync::Arc,
@@ -30,14 +31,53 @@ impl<C> Clone for WebInterface<C> {
     }
 }
 
-async fn handle_request(uri: Uri, json: Option<Json<String>>) -> impl IntoResponse {
-    Html(format!("<h1>Hey `{uri}`<h1><p>{json:?}</p>\n"))
+async fn handle_request(
+    uri: Uri,
+    headers: HeaderMap,
+    json: Option<Json<String>>,
+) -> impl IntoResponse {
+    let request_id = match headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+    {
+        Some(id) => format!("<p>Request ID: <code>{id}</code></p>"),
+        None => String::new(),
+    };
+
+    Html(format!("<h1>Hey `{uri}`<h1><p>{json:?}</p>{request_id}\n"))
 }
 
-impl<C> WebInterface<C> {
+/// Whether the configuration has problems. The page is public, so the problems themselves are
+/// only shown by `sail status`.
+async fn status<C>(configuration: Arc<C>) -> impl IntoResponse
+where
+    C: Configurable,
+{
+    let problems = configuration.problems();
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    let warnings = problems.len() - errors;
+
+    let state = match errors {
+        0 => "OK",
+        _ => "Degraded, files with errors were skipped",
+    };
+
+    Html(format!(
+        "<h1>Status</h1><p>{state}</p><p>{errors} errors, {warnings} warnings in the configuration, see <code>sail status</code>.</p>\n"
+    ))
+}
+
+impl<C> WebInterface<C>
+where
+    C: Configurable + Send + Sync + 'static,
+{
     pub fn new(configuration: Arc<C>) -> Self {
+        let status_configuration = configuration.clone();
+
         Self {
-            router: Router::new().fallback(handle_request),
+            router: Router::new()
+                .route("/status", get(move || status(status_configuration.clone())))
+                .fallback(handle_request),
 
             configuration,
         }